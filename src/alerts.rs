@@ -2,10 +2,7 @@ use crate::emoji::named::*;
 use regex::Regex;
 use std::{
     sync::{
-        atomic::{
-            AtomicU64,
-            Ordering::{Acquire, Relaxed},
-        },
+        atomic::Ordering::{Acquire, Relaxed},
         LazyLock,
     },
     time::Duration,
@@ -16,7 +13,7 @@ use tokio::{
 };
 pub async fn run() {
     let (tx, mut rx) = tokio::sync::broadcast::channel::<u64>(10);
-    static COUNT: AtomicU64 = AtomicU64::new(0);
+    use crate::metrics::SYN as COUNT;
     let mut f =
         tokio::io::BufReader::new(tokio::fs::File::open("/var/log/kern.log").await.unwrap());
     let mut buf = [0; 1 << 20];
@@ -52,22 +49,26 @@ pub async fn run() {
     let wh =
         std::env::var("AOOK").unwrap_or(std::fs::read_to_string("aook").expect("wher webhook"));
     let wh = crate::webhook::Webhook::new(&http, &wh).await;
-    loop {
-        match rx.try_recv() {
-            Ok(x) => {
-                wh.send(|m| {
-                    m.content(&format!(
-                        "{WARNING} <@&1202414272030974033> attacked by {x} bots/s"
-                    ))
-                })
-                .await;
-            }
-            Err(TryRecvError::Closed) => panic!(),
-            Err(TryRecvError::Lagged(_)) => continue,
-            Err(TryRecvError::Empty) => {
-                tokio::time::sleep(Duration::from_secs(4)).await;
-                continue;
+    let alert = async {
+        loop {
+            match rx.try_recv() {
+                Ok(x) => {
+                    wh.send(|m| {
+                        m.content(&format!(
+                            "{WARNING} <@&1202414272030974033> attacked by {x} bots/s"
+                        ))
+                    })
+                    .await;
+                }
+                Err(TryRecvError::Closed) => panic!(),
+                Err(TryRecvError::Lagged(_)) => continue,
+                Err(TryRecvError::Empty) => {
+                    tokio::time::sleep(Duration::from_secs(4)).await;
+                    continue;
+                }
             }
         }
-    }
+    };
+    // sends what `alert` queues
+    tokio::join!(wh.drain(), alert);
 }
@@ -0,0 +1,195 @@
+//! `/api/*`: what the bot knows, as json, for the website and whatever else.
+use crate::auth::{Access, Scope};
+use crate::bot::{
+    lb,
+    maps::Maps,
+    player::{Player, Players},
+    rules, status,
+};
+use crate::cache::Fresh;
+use crate::console::Error;
+use crate::server::{pick, unavailable, Servers, State as Server};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header::*, StatusCode},
+    response::{AppendHeaders, IntoResponse},
+    routing::get,
+    Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// how long answers are shared for
+const RECENT: Duration = Duration::from_secs(5);
+
+/// what the console last said, per server. anyone can poll `/api`, so they all get this,
+/// rather than a command each holding up the bot.
+pub struct Recent {
+    status: Fresh<(u32, u32, u32)>,
+    players: Fresh<Vec<Player>>,
+    survivors: Fresh<Vec<lb::Entry>>,
+    infected: Fresh<Vec<lb::Entry>>,
+}
+
+impl Recent {
+    pub const fn new() -> Self {
+        Self {
+            status: Fresh::new(RECENT),
+            players: Fresh::new(RECENT),
+            survivors: Fresh::new(RECENT),
+            infected: Fresh::new(RECENT),
+        }
+    }
+
+    /// `(tps, memory, players)`
+    pub async fn status(&self, s: &Server) -> anyhow::Result<(u32, u32, u32)> {
+        self.status
+            .get(|| async {
+                let block = s.console.request("status").await?;
+                Ok(status::parse(&block)
+                    .ok_or_else(|| Error::unparsable("status", block.trim()))?)
+            })
+            .await
+    }
+
+    pub async fn players(&self, s: &Server) -> anyhow::Result<Vec<Player>> {
+        self.players
+            .get(|| async { Ok(Players::get_all(s).await?.clone()) })
+            .await
+    }
+
+    pub async fn leaderboard(&self, s: &Server, team: lb::Team) -> anyhow::Result<Vec<lb::Entry>> {
+        match team {
+            lb::Team::Survivor => &self.survivors,
+            lb::Team::Infected => &self.infected,
+        }
+        .get(|| lb::entries(s, team))
+        .await
+    }
+}
+
+type Api = Result<Json, StatusCode>;
+
+pub struct Json(pub Value);
+
+impl IntoResponse for Json {
+    fn into_response(self) -> axum::response::Response {
+        (
+            AppendHeaders([
+                (CONTENT_TYPE, "application/json"),
+                (ACCESS_CONTROL_ALLOW_ORIGIN, "*"),
+            ]),
+            self.0.to_string(),
+        )
+            .into_response()
+    }
+}
+
+/// `/api/status`: `{"tps", "memory", "players"}`, memory in MB. like the rest, up to 5s old.
+async fn status(State(servers): State<Arc<Servers>>, server: Option<Path<String>>) -> Api {
+    let s = pick(&servers, server)?;
+    let (tps, memory, players) = s.recent.status(&s).await.map_err(unavailable)?;
+    Ok(Json(json!({
+        "server": s.name,
+        "link": s.console.link().to_string(),
+        "tps": tps,
+        "memory": memory,
+        "players": players,
+    })))
+}
+
+/// `/api/players`: `[{"name", "admin"}]`, plus `"uuid"` and `"ip"` for moderators.
+async fn players(
+    State(servers): State<Arc<Servers>>,
+    server: Option<Path<String>>,
+    access: Access,
+) -> Api {
+    let s = pick(&servers, server)?;
+    let full = access.can(Scope::Moderate);
+    let players = s.recent.players(&s).await.map_err(unavailable)?;
+    Ok(Json(players.iter().map(|p| player(p, full)).collect()))
+}
+
+/// uuids and ips are only for moderators
+fn player(p: &Player, full: bool) -> Value {
+    let mut v = json!({ "name": p.name, "admin": p.admin });
+    if full {
+        v["uuid"] = json!(p.uuid);
+        v["ip"] = json!(p.ip.to_string());
+    }
+    v
+}
+
+/// `/api/maps`: the map names, in `host` order.
+async fn maps(State(servers): State<Arc<Servers>>, server: Option<Path<String>>) -> Api {
+    let s = pick(&servers, server)?;
+    Ok(Json(json!(Maps::get_all(&s).await.map_err(unavailable)?)))
+}
+
+/// `/api/rules`: the rules that are set, as mindustry names them.
+async fn rules(State(servers): State<Arc<Servers>>, server: Option<Path<String>>) -> Api {
+    let s = pick(&servers, server)?;
+    let rules = rules::rules(&s).await.map_err(unavailable)?;
+    Ok(Json(json!(&*rules)))
+}
+
+#[derive(serde_derive::Deserialize)]
+struct Board {
+    /// `survivors` (the default) or `infected`
+    team: Option<lb::Team>,
+}
+
+/// `/api/leaderboard?team=infected`: `[{"place", "rank", "name", "wins"}]`.
+async fn leaderboard(
+    State(servers): State<Arc<Servers>>,
+    server: Option<Path<String>>,
+    Query(board): Query<Board>,
+) -> Api {
+    let s = pick(&servers, server)?;
+    let team = board.team.unwrap_or(lb::Team::Survivor);
+    Ok(Json(json!(s
+        .recent
+        .leaderboard(&s, team)
+        .await
+        .map_err(unavailable)?)))
+}
+
+pub fn router() -> Router<Arc<Servers>> {
+    Router::new()
+        .route("/api/status", get(status))
+        .route("/api/status/:server", get(status))
+        .route("/api/players", get(players))
+        .route("/api/players/:server", get(players))
+        .route("/api/maps", get(maps))
+        .route("/api/maps/:server", get(maps))
+        .route("/api/rules", get(rules))
+        .route("/api/rules/:server", get(rules))
+        .route("/api/leaderboard", get(leaderboard))
+        .route("/api/leaderboard/:server", get(leaderboard))
+}
+
+#[test]
+fn redact() {
+    let p = Player {
+        admin: true,
+        name: "bendn".into(),
+        uuid: "+41521zhHB8321xAbXYedw==".into(),
+        ip: [10, 0, 0, 1].into(),
+    };
+    assert_eq!(player(&p, false), json!({ "name": "bendn", "admin": true }));
+    assert_eq!(player(&p, true)["ip"], "10.0.0.1");
+}
+
+#[tokio::test]
+async fn shared() {
+    let fake = crate::fake::Fake::script(crate::fake::CONSOLE)
+        .spawn()
+        .await;
+    let (s, _stdout) = fake.server();
+    for _ in 0..3 {
+        assert_eq!(s.recent.status(&s).await.unwrap(), (57, 274, 2));
+        assert_eq!(s.recent.players(&s).await.unwrap().len(), 2);
+    }
+    assert_eq!(fake.heard(), ["status", "players"]);
+}
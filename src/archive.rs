@@ -0,0 +1,293 @@
+//! save snapshots, taken every so often and kept for a while, so theres something to look
+//! back at after a grief.
+use crate::api::Json;
+use crate::auth::{Guard, Scope};
+use crate::bot::maps::{render, savefile};
+use crate::cache::{gzips, Cached};
+use crate::server::{pick, unavailable, Servers, State};
+use anyhow::Result;
+use axum::{
+    extract::{Path, Query},
+    http::{header::*, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+    routing::get,
+    Router,
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+/// a save in the archive
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde_derive::Serialize)]
+pub struct Snapshot {
+    /// unix seconds
+    pub at: u64,
+    /// compressed
+    pub size: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// one servers snapshots, as `<dir>/<unix seconds>.msav.gz`.
+pub struct Archive {
+    dir: PathBuf,
+    /// `SNAPSHOT_EVERY`: time between snapshots, zero for none
+    every: Duration,
+    /// `SNAPSHOT_KEEP`: how long theyre kept
+    keep: Duration,
+    /// the last one rendered
+    rendered: Mutex<Option<(u64, Arc<Vec<u8>>)>>,
+}
+
+impl Archive {
+    /// `SNAPSHOTS/<name>` (`snapshots` by default).
+    pub fn from_env(name: &str) -> Self {
+        let duration = |var: &str, default| {
+            std::env::var(var).map_or(default, |x| {
+                parse_duration::parse(&x)
+                    .unwrap_or_else(|e| panic!("{var} should be a duration: {e}"))
+            })
+        };
+        Self::new(
+            PathBuf::from(std::env::var("SNAPSHOTS").unwrap_or_else(|_| "snapshots".into()))
+                .join(name),
+            duration("SNAPSHOT_EVERY", Duration::from_secs(10 * 60)),
+            duration("SNAPSHOT_KEEP", Duration::from_secs(2 * 24 * 60 * 60)),
+        )
+    }
+
+    fn new(dir: PathBuf, every: Duration, keep: Duration) -> Self {
+        Self {
+            dir,
+            every,
+            keep,
+            rendered: Mutex::const_new(None),
+        }
+    }
+
+    fn path(&self, at: u64) -> PathBuf {
+        self.dir.join(format!("{at}.msav.gz"))
+    }
+
+    /// oldest first
+    pub fn list(&self) -> Vec<Snapshot> {
+        let Ok(dir) = std::fs::read_dir(&self.dir) else {
+            return vec![];
+        };
+        let mut all = dir
+            .filter_map(|f| {
+                let f = f.ok()?;
+                let at = f
+                    .file_name()
+                    .to_str()?
+                    .strip_suffix(".msav.gz")?
+                    .parse()
+                    .ok()?;
+                Some(Snapshot {
+                    at,
+                    size: f.metadata().ok()?.len(),
+                })
+            })
+            .collect::<Vec<_>>();
+        all.sort_by_key(|s| s.at);
+        all
+    }
+
+    /// the last snapshot taken at or before `at`
+    pub fn before(&self, at: u64) -> Option<Snapshot> {
+        self.list().into_iter().rev().find(|s| s.at <= at)
+    }
+
+    /// the compressed save
+    pub fn read(&self, at: u64) -> std::io::Result<Vec<u8>> {
+        std::fs::read(self.path(at))
+    }
+
+    pub fn load(&self, at: u64) -> std::io::Result<Vec<u8>> {
+        let mut save = vec![];
+        GzDecoder::new(&*self.read(at)?).read_to_end(&mut save)?;
+        Ok(save)
+    }
+
+    fn take(&self, save: &[u8], at: u64) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let mut e = GzEncoder::new(vec![], Compression::default());
+        e.write_all(save)?;
+        // so a half written snapshot is never listed
+        let part = self.dir.join(format!("{at}.part"));
+        std::fs::write(&part, e.finish()?)?;
+        std::fs::rename(part, self.path(at))
+    }
+
+    /// forget snapshots older than `keep`
+    fn prune(&self, now: u64) {
+        define_print!("archive");
+        for s in self.list() {
+            if s.at + self.keep.as_secs() < now {
+                if let Err(e) = std::fs::remove_file(self.path(s.at)) {
+                    let path = self.path(s.at);
+                    let path = path.display();
+                    output!("couldnt remove {path}: {e}");
+                }
+            }
+        }
+    }
+
+    /// the snapshot, drawn
+    pub async fn render(&self, at: u64) -> Result<Arc<Vec<u8>>> {
+        let mut rendered = self.rendered.lock().await;
+        if let Some((then, png)) = &*rendered
+            && *then == at
+        {
+            return Ok(png.clone());
+        }
+        let save = self.load(at)?;
+        let ((png, _), _) = tokio::task::spawn_blocking(move || render(&save)).await??;
+        let png = Arc::new(png);
+        *rendered = Some((at, png.clone()));
+        Ok(png)
+    }
+}
+
+/// snapshot `s` forever.
+pub async fn run(s: Arc<State>) {
+    define_print!("archive");
+    let name = &s.name;
+    if s.archive.every.is_zero() {
+        return;
+    }
+    let mut every = tokio::time::interval(s.archive.every);
+    every.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        every.tick().await;
+        let save = match savefile(&s).await {
+            Ok(save) => save,
+            Err(e) => {
+                output!("{name}: no snapshot: {e:#}");
+                continue;
+            }
+        };
+        let state = s.clone();
+        let took = tokio::task::spawn_blocking(move || {
+            let now = now();
+            state.archive.take(&save, now)?;
+            state.archive.prune(now);
+            std::io::Result::Ok(())
+        })
+        .await
+        .unwrap();
+        if let Err(e) = took {
+            output!("{name}: couldnt write snapshot: {e}");
+        }
+    }
+}
+
+#[derive(serde_derive::Deserialize)]
+struct Which {
+    server: Option<String>,
+}
+
+/// `/saves?server=`: `[{"at", "size"}]`, oldest first.
+async fn saves(
+    axum::extract::State(servers): axum::extract::State<Arc<Servers>>,
+    Query(which): Query<Which>,
+) -> Result<Json, StatusCode> {
+    let s = pick(&servers, which.server.map(Path))?;
+    Ok(Json(serde_json::json!(s.archive.list())))
+}
+
+/// `/saves/<at>?server=`: the save (gzipped, if the client takes it).
+async fn save(
+    axum::extract::State(servers): axum::extract::State<Arc<Servers>>,
+    Path(at): Path<u64>,
+    Query(which): Query<Which>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let s = pick(&servers, which.server.map(Path))?;
+    let gz = s.archive.read(at).map_err(|_| StatusCode::NOT_FOUND)?;
+    let cached = snapshot(at);
+    // its already gzipped, so dont bother with Cached::reply's
+    let gzip = gzips(&headers);
+    // a 304 doesnt need it unpacked
+    let body = if gzip || cached.fresh(&headers) {
+        gz
+    } else {
+        let mut save = vec![];
+        GzDecoder::new(&*gz)
+            .read_to_end(&mut save)
+            .map_err(|e| unavailable(e.into()))?;
+        save
+    };
+    let mut res = cached.reply(&headers, "application/octet-stream", || body, false);
+    if gzip && res.status() == StatusCode::OK {
+        res.headers_mut()
+            .insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    }
+    res.headers_mut()
+        .insert(VARY, HeaderValue::from_static("accept-encoding"));
+    Ok(res)
+}
+
+/// `/saves/<at>/view?server=`: the save, drawn.
+async fn view(
+    axum::extract::State(servers): axum::extract::State<Arc<Servers>>,
+    Path(at): Path<u64>,
+    Query(which): Query<Which>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let s = pick(&servers, which.server.map(Path))?;
+    if !s.archive.path(at).exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let png = s.archive.render(at).await.map_err(unavailable)?;
+    Ok(snapshot(at).reply(&headers, "image/png", || (*png).clone(), false))
+}
+
+/// snapshots never change
+fn snapshot(at: u64) -> Cached {
+    Cached {
+        etag: format!("\"{at}\""),
+        modified: UNIX_EPOCH + Duration::from_secs(at),
+        control: "private, max-age=31536000, immutable".into(),
+    }
+}
+
+pub fn router() -> Router<Arc<Servers>> {
+    Router::new()
+        .route("/saves", get(saves))
+        .route("/saves/:at", get(save))
+        .route("/saves/:at/view", get(view))
+        .need(Scope::Read)
+}
+
+#[test]
+fn archive() {
+    let dir = std::env::temp_dir().join(format!("panel-archive-{}", std::process::id()));
+    let a = Archive::new(
+        dir.clone(),
+        Duration::from_secs(60),
+        Duration::from_secs(100),
+    );
+    assert!(a.list().is_empty());
+    a.take(b"MSAV one", 1000).unwrap();
+    a.take(b"MSAV two", 1060).unwrap();
+    std::fs::write(dir.join("notes.txt"), "not a snapshot").unwrap();
+    assert_eq!(
+        a.list().iter().map(|s| s.at).collect::<Vec<_>>(),
+        [1000, 1060]
+    );
+    assert_eq!(a.load(1060).unwrap(), b"MSAV two");
+    assert_eq!(a.before(1059).map(|s| s.at), Some(1000));
+    assert_eq!(a.before(999), None);
+    a.prune(1150);
+    assert_eq!(a.list().iter().map(|s| s.at).collect::<Vec<_>>(), [1060]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
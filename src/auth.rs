@@ -0,0 +1,425 @@
+//! who may do what on the web server: api tokens and logins, each with a [`Scope`].
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{ConnectInfo, FromRequestParts, State},
+    http::{header::*, request::Parts, HeaderMap, Request, StatusCode},
+    middleware::{self, Next},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+    Form, Router,
+};
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// what a token (or login) is allowed to do. each scope includes the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde_derive::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// look at things, like the save
+    Read,
+    /// see player ips, read the console
+    Moderate,
+    /// run commands
+    Admin,
+}
+
+#[derive(serde_derive::Deserialize)]
+struct Token {
+    token: String,
+    scope: Scope,
+}
+
+#[derive(serde_derive::Deserialize)]
+struct User {
+    name: String,
+    /// argon2, from `panel hash`
+    hash: String,
+    scope: Scope,
+}
+
+/// `auth.json` (or `$AUTH`).
+#[derive(Default, serde_derive::Deserialize)]
+struct Config {
+    #[serde(default)]
+    tokens: Vec<Token>,
+    #[serde(default)]
+    users: Vec<User>,
+}
+
+impl Config {
+    fn load() -> Self {
+        let path = std::env::var("AUTH").unwrap_or_else(|_| "auth.json".into());
+        let mut config: Self = read_to_string(&path).map_or_else(
+            |_| Self::default(),
+            |x| serde_json::from_str(&x).unwrap_or_else(|e| panic!("{path} isnt valid: {e}")),
+        );
+        // `CONSOLE_TOKEN`, or the `console_token` file, from before there were scopes
+        if let Some(token) = std::env::var("CONSOLE_TOKEN")
+            .or_else(|_| read_to_string("console_token"))
+            .ok()
+            .map(|x| x.trim().to_owned())
+            .filter(|x| !x.is_empty())
+        {
+            config.tokens.push(Token {
+                token,
+                scope: Scope::Admin,
+            });
+        }
+        config
+    }
+}
+
+/// failures allowed per [`WINDOW`], per address
+const LIMIT: u32 = 5;
+const WINDOW: Duration = Duration::from_secs(5 * 60);
+/// how long a login lasts
+const SESSION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const SESSION_COOKIE: &str = "panel";
+
+pub struct Keys {
+    config: Config,
+    /// session id -> (user, scope, expiry)
+    sessions: Mutex<HashMap<String, (String, Scope, Instant)>>,
+    /// address -> (failures, since)
+    failures: Mutex<HashMap<Option<IpAddr>, (u32, Instant)>>,
+}
+
+static KEYS: LazyLock<Keys> = LazyLock::new(|| Keys::new(Config::load()));
+/// checked against for users that dont exist
+static DUMMY: LazyLock<String> = LazyLock::new(|| hash("dummy"));
+
+/// compares without giving away how much matched
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// for `users` in `auth.json`
+pub fn hash(password: &str) -> String {
+    Argon2::default()
+        .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+        .unwrap()
+        .to_string()
+}
+
+impl Keys {
+    fn new(config: Config) -> Self {
+        Self {
+            config,
+            sessions: Mutex::new(HashMap::new()),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn token(&self, given: &str) -> Option<Scope> {
+        // check them all, so the time doesnt say which matched
+        self.config
+            .tokens
+            .iter()
+            .filter(|t| same(t.token.as_bytes(), given.as_bytes()))
+            .map(|t| t.scope)
+            .max()
+    }
+
+    /// a new session, if the password is right
+    fn login(&self, user: &str, password: &str) -> Option<String> {
+        let found = self.config.users.iter().find(|u| u.name == user);
+        // unknown users are checked against a dummy, so the time doesnt say who exists
+        let hash = PasswordHash::new(found.map_or(&*DUMMY, |u| &u.hash))
+            .map_err(|e| println!("web: {user}s hash is broken: {e}"))
+            .ok()?;
+        let right = Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok();
+        let user = found.filter(|_| right)?;
+        let mut id = [0; 32];
+        OsRng.fill_bytes(&mut id);
+        let id = id.iter().fold(String::new(), |mut s, b| {
+            use std::fmt::Write;
+            write!(s, "{b:02x}").unwrap();
+            s
+        });
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, (_, _, until)| *until > Instant::now());
+        sessions.insert(
+            id.clone(),
+            (user.name.clone(), user.scope, Instant::now() + SESSION),
+        );
+        Some(id)
+    }
+
+    fn session(&self, id: &str) -> Option<Scope> {
+        self.who(id).map(|(_, scope)| scope)
+    }
+
+    /// who is logged in as `id`
+    fn who(&self, id: &str) -> Option<(String, Scope)> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|(_, _, until)| *until > Instant::now())
+            .map(|(name, scope, _)| (name.clone(), *scope))
+    }
+
+    fn logout(&self, id: &str) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+
+    fn locked(&self, ip: Option<IpAddr>) -> bool {
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, (_, since)| since.elapsed() < WINDOW);
+        failures.get(&ip).is_some_and(|&(n, _)| n >= LIMIT)
+    }
+
+    fn failed(&self, ip: Option<IpAddr>) {
+        let mut failures = self.failures.lock().unwrap();
+        let (n, _) = failures.entry(ip).or_insert((0, Instant::now()));
+        *n += 1;
+        if *n == LIMIT {
+            println!("web: too many failed logins from {ip:?}");
+        }
+    }
+}
+
+fn ip(parts: &Parts) -> Option<IpAddr> {
+    parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(at)| at.ip())
+}
+
+fn cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(';'))
+        .find_map(|x| x.trim().strip_prefix(SESSION_COOKIE)?.strip_prefix('='))
+}
+
+/// a token given some other way than a header (like a websockets first message).
+/// wrong ones are counted, like any other.
+pub fn given(token: &str, ip: Option<IpAddr>) -> Option<Scope> {
+    if KEYS.locked(ip) {
+        return None;
+    }
+    let scope = KEYS.token(token);
+    if scope.is_none() {
+        KEYS.failed(ip);
+    }
+    scope
+}
+
+/// what a request may do: the best of its token (`Authorization: Bearer`) and its login.
+/// a wrong token is refused (and counted), rather than ignored.
+pub struct Access(pub Option<Scope>);
+
+impl Access {
+    pub fn can(&self, scope: Scope) -> bool {
+        self.0 >= Some(scope)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Access {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, StatusCode> {
+        let ip = ip(parts);
+        if KEYS.locked(ip) {
+            return Err(StatusCode::TOO_MANY_REQUESTS);
+        }
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|x| x.to_str().ok()?.strip_prefix("Bearer "))
+            .map(str::to_owned);
+        let token = match token {
+            Some(token) => match KEYS.token(&token) {
+                Some(scope) => Some(scope),
+                None => {
+                    KEYS.failed(ip);
+                    return Err(StatusCode::UNAUTHORIZED);
+                }
+            },
+            None => None,
+        };
+        let login = cookie(&parts.headers).and_then(|id| KEYS.session(id));
+        Ok(Self(token.max(login)))
+    }
+}
+
+/// someone logged in, for things done in their name (tokens dont have one).
+pub struct Session {
+    pub name: String,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Session {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, StatusCode> {
+        let (name, _) = cookie(&parts.headers)
+            .and_then(|id| KEYS.who(id))
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        Ok(Self { name })
+    }
+}
+
+async fn need(
+    State(scope): State<Scope>,
+    access: Access,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    match access.0 {
+        Some(has) if has >= scope => next.run(req).await,
+        Some(_) => StatusCode::FORBIDDEN.into_response(),
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// how routes say who may use them.
+pub trait Guard {
+    /// refuse requests without `scope`
+    fn need(self, scope: Scope) -> Self;
+}
+
+impl<S: Clone + Send + Sync + 'static> Guard for Router<S> {
+    fn need(self, scope: Scope) -> Self {
+        self.route_layer(middleware::from_fn_with_state(scope, need))
+    }
+}
+
+#[derive(serde_derive::Deserialize)]
+pub struct Login {
+    user: String,
+    password: String,
+    /// where to go after
+    #[serde(default)]
+    to: String,
+}
+
+/// only to here, not elsewhere. browsers read `\` as `/` and drop tabs and newlines,
+/// so `/\evil.com` is as far away as `//evil.com`.
+fn local(to: &str) -> &str {
+    if to.starts_with('/')
+        && !to.starts_with("//")
+        && !to.chars().any(|c| c == '\\' || c.is_control())
+    {
+        to
+    } else {
+        "/"
+    }
+}
+
+/// `POST /login`, from the login page.
+pub async fn login(at: Option<ConnectInfo<SocketAddr>>, Form(login): Form<Login>) -> Response {
+    let ip = at.map(|ConnectInfo(at)| at.ip());
+    if KEYS.locked(ip) {
+        return StatusCode::TOO_MANY_REQUESTS.into_response();
+    }
+    let Some(id) = KEYS.login(&login.user, &login.password) else {
+        KEYS.failed(ip);
+        return Redirect::to("/login#failed").into_response();
+    };
+    println!("web: {} logged in", login.user);
+    let to = local(&login.to);
+    (
+        AppendHeaders([(
+            SET_COOKIE,
+            format!(
+                "{SESSION_COOKIE}={id}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}",
+                SESSION.as_secs()
+            ),
+        )]),
+        Redirect::to(to),
+    )
+        .into_response()
+}
+
+/// `POST /logout`
+pub async fn logout(headers: HeaderMap) -> impl IntoResponse {
+    if let Some(id) = cookie(&headers) {
+        KEYS.logout(id);
+    }
+    (
+        AppendHeaders([(
+            SET_COOKIE,
+            format!("{SESSION_COOKIE}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0"),
+        )]),
+        Redirect::to("/login"),
+    )
+}
+
+#[test]
+fn compare() {
+    assert!(same(b"hunter2", b"hunter2"));
+    assert!(!same(b"hunter2", b"hunter3"));
+    assert!(!same(b"hunter2", b"hunter"));
+}
+
+#[test]
+fn keys() {
+    let keys = Keys::new(Config {
+        tokens: vec![
+            Token {
+                token: "website".into(),
+                scope: Scope::Read,
+            },
+            Token {
+                token: "bot".into(),
+                scope: Scope::Admin,
+            },
+        ],
+        users: vec![User {
+            name: "bendn".into(),
+            hash: hash("hunter2"),
+            scope: Scope::Moderate,
+        }],
+    });
+    assert_eq!(keys.token("website"), Some(Scope::Read));
+    assert_eq!(keys.token("bot"), Some(Scope::Admin));
+    assert_eq!(keys.token("webs"), None);
+    assert!(Access(Some(Scope::Admin)).can(Scope::Moderate));
+    assert!(!Access(Some(Scope::Read)).can(Scope::Moderate));
+    assert!(!Access(None).can(Scope::Read));
+
+    assert!(keys.login("bendn", "hunter3").is_none());
+    assert!(keys.login("nile", "hunter2").is_none());
+    let id = keys.login("bendn", "hunter2").unwrap();
+    assert_eq!(keys.session(&id), Some(Scope::Moderate));
+    assert_eq!(keys.who(&id), Some(("bendn".into(), Scope::Moderate)));
+    keys.logout(&id);
+    assert_eq!(keys.session(&id), None);
+
+    let ip = Some(IpAddr::from([10, 0, 0, 1]));
+    for _ in 0..LIMIT {
+        assert!(!keys.locked(ip));
+        keys.failed(ip);
+    }
+    assert!(keys.locked(ip));
+    assert!(!keys.locked(None));
+}
+
+#[test]
+fn cookies() {
+    let mut headers = HeaderMap::new();
+    headers.insert(COOKIE, "theme=dark; panel=abc".parse().unwrap());
+    assert_eq!(cookie(&headers), Some("abc"));
+}
+
+#[test]
+fn redirects() {
+    assert_eq!(local("/console?server=plague"), "/console?server=plague");
+    assert_eq!(local("https://evil.com"), "/");
+    assert_eq!(local("//evil.com"), "/");
+    assert_eq!(local("/\\evil.com"), "/");
+    assert_eq!(local("/\t/evil.com"), "/");
+}
@@ -0,0 +1,311 @@
+//! avatars for relayed players. players linked to a discord account get its avatar, everyone
+//! else gets one made from their uuid, at `/avatar/<id>.png`. links are in `links.json`
+//! (or `$LINKS`), edited with `/relay link`.
+use crate::cache::Cached;
+use crate::view::{png, Raw};
+use anyhow::Result;
+use axum::{
+    extract::Path,
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use poise::serenity_prelude::{Http, UserId};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+/// cells per side
+const CELLS: u32 = 5;
+/// pixels per cell
+const CELL: u32 = 16;
+/// how long a discord avatar is used before asking again
+const TTL: Duration = Duration::from_secs(60 * 60);
+/// how long after discord wouldnt say to ask again
+const RETRY: Duration = Duration::from_secs(5 * 60);
+/// names remembered, so it doesnt grow forever
+const NAMES: usize = 10_000;
+
+/// `PUBLIC_URL`: where discord can reach the panel, like `https://panel.example.com`.
+/// without it, unlinked players keep the webhooks avatar.
+static PUBLIC_URL: LazyLock<Option<String>> = LazyLock::new(|| {
+    std::env::var("PUBLIC_URL")
+        .ok()
+        .map(|x| x.trim_end_matches('/').to_owned())
+});
+
+/// fnv-1a, as the ids have to stay the same between builds
+fn hash(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// whose face a player gets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Face {
+    /// a discord user
+    Linked(u64),
+    /// [`identicon`]
+    Made(u64),
+}
+
+pub struct Avatars {
+    /// where links are saved. none in tests
+    path: Option<PathBuf>,
+    /// uuid -> discord user
+    links: Mutex<HashMap<String, u64>>,
+    /// name -> uuid, from joins
+    uuids: Mutex<HashMap<String, String>>,
+    /// discord user -> (avatar url, if discord said, when it was asked for)
+    faces: Mutex<HashMap<u64, (Option<String>, Instant)>>,
+}
+
+pub static AVATARS: LazyLock<Avatars> = LazyLock::new(Avatars::load);
+
+impl Avatars {
+    fn load() -> Self {
+        let path = PathBuf::from(std::env::var("LINKS").unwrap_or_else(|_| "links.json".into()));
+        let links = std::fs::read_to_string(&path).map_or_else(
+            |_| HashMap::new(),
+            |x| {
+                serde_json::from_str(&x)
+                    .unwrap_or_else(|e| panic!("{} isnt valid: {e}", path.display()))
+            },
+        );
+        let mut a = Self::new(links);
+        a.path = Some(path);
+        a
+    }
+
+    fn new(links: HashMap<String, u64>) -> Self {
+        Self {
+            path: None,
+            links: Mutex::new(links),
+            uuids: Mutex::new(HashMap::new()),
+            faces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn save(&self, links: &HashMap<String, u64>) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        std::fs::write(path, serde_json::to_string_pretty(links)?)?;
+        Ok(())
+    }
+
+    /// chat only has names, so remember whose they are
+    pub fn joined(&self, player: &str, uuid: &str) {
+        let mut uuids = self.uuids.lock().unwrap();
+        if uuids.len() >= NAMES {
+            uuids.clear();
+        }
+        uuids.insert(player.to_owned(), uuid.to_owned());
+    }
+
+    /// give the player with `uuid` the avatar of `user`.
+    pub fn link(&self, uuid: &str, user: u64) -> Result<()> {
+        let mut links = self.links.lock().unwrap();
+        links.insert(uuid.to_owned(), user);
+        self.save(&links)
+    }
+
+    /// forget every player linked to `user`. returns how many there were.
+    pub fn unlink(&self, user: u64) -> Result<usize> {
+        let mut links = self.links.lock().unwrap();
+        let before = links.len();
+        links.retain(|_, u| *u != user);
+        let gone = before - links.len();
+        if gone > 0 {
+            self.save(&links)?;
+        }
+        Ok(gone)
+    }
+
+    fn face(&self, player: &str) -> Face {
+        let uuid = self.uuids.lock().unwrap().get(player).cloned();
+        match uuid {
+            Some(uuid) => match self.links.lock().unwrap().get(&uuid) {
+                Some(&user) => Face::Linked(user),
+                None => Face::Made(hash(&uuid)),
+            },
+            // joined before the panel was watching
+            None => Face::Made(hash(player)),
+        }
+    }
+
+    fn made(id: u64) -> Option<String> {
+        PUBLIC_URL
+            .as_ref()
+            .map(|base| format!("{base}/avatar/{id:016x}.png"))
+    }
+
+    fn cached(&self, user: u64) -> Option<Option<String>> {
+        self.faces
+            .lock()
+            .unwrap()
+            .get(&user)
+            .filter(|(url, when)| when.elapsed() < if url.is_some() { TTL } else { RETRY })
+            .map(|(url, _)| url.clone())
+    }
+
+    /// the avatar url for `player`, if theres one. `http` has to be logged in to look up users.
+    pub async fn url(&self, http: &Http, player: &str) -> Option<String> {
+        self.url_with(player, |user| async move {
+            Ok(http.get_user(UserId::new(user)).await?.face())
+        })
+        .await
+    }
+
+    /// [`url`](Self::url), asking `lookup` for discord avatars
+    async fn url_with<F: Future<Output = Result<String>>>(
+        &self,
+        player: &str,
+        lookup: impl FnOnce(u64) -> F,
+    ) -> Option<String> {
+        let user = match self.face(player) {
+            Face::Linked(user) => user,
+            Face::Made(id) => return Self::made(id),
+        };
+        let face = match self.cached(user) {
+            Some(face) => face,
+            None => {
+                let face = match lookup(user).await {
+                    Ok(url) => Some(url),
+                    Err(e) => {
+                        println!("avatars: couldnt get {user}: {e:#}");
+                        None
+                    }
+                };
+                // failures too, so a user discord wont show isnt asked for every line
+                self.faces
+                    .lock()
+                    .unwrap()
+                    .insert(user, (face.clone(), Instant::now()));
+                face
+            }
+        };
+        face.or_else(|| {
+            let uuid = self.uuids.lock().unwrap().get(player).cloned();
+            Self::made(hash(uuid.as_deref().unwrap_or(player)))
+        })
+    }
+}
+
+/// a mirrored 5x5 grid, in a color picked by `id`.
+pub fn identicon(id: u64) -> Raw {
+    let side = CELLS * CELL;
+    let [r, g, b, ..] = id.to_le_bytes();
+    // bright enough to see on the background
+    let fg = [r, g, b].map(|c| 96 + c % 160);
+    let bg = [62, 59, 59];
+    let mut rgb = Vec::with_capacity((side * side * 3) as usize);
+    for y in 0..side {
+        for x in 0..side {
+            let (row, col) = (y / CELL, x / CELL);
+            // the right side is the left, flipped
+            let col = col.min(CELLS - 1 - col);
+            let on = id >> (32 + row * 3 + col) & 1 == 1;
+            rgb.extend(if on { fg } else { bg });
+        }
+    }
+    Raw {
+        width: side,
+        height: side,
+        rgb,
+    }
+}
+
+/// `/avatar/<id>.png`
+pub async fn avatar(Path(file): Path<String>, headers: HeaderMap) -> Result<Response, StatusCode> {
+    let id = file
+        .strip_suffix(".png")
+        .filter(|x| x.len() == 16)
+        .and_then(|x| u64::from_str_radix(x, 16).ok())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    // the same id is always the same picture
+    let cached = Cached {
+        etag: format!("\"{id:016x}\""),
+        modified: UNIX_EPOCH,
+        control: "public, max-age=31536000, immutable".into(),
+    };
+    Ok(cached.reply(&headers, "image/png", || png(&identicon(id)), false))
+}
+
+#[test]
+fn avatars() {
+    let a = Avatars::new(HashMap::from([(
+        "JjvYb8x7FWSV+zA0VsuHlw==".into(),
+        600014432298598400,
+    )]));
+    assert_eq!(a.face("nile"), Face::Made(hash("nile")));
+    a.joined("nile", "JjvYb8x7FWSV+zA0VsuHlw==");
+    a.joined("bendn", "+41521zhHB8321xAbXYedw==");
+    assert_eq!(a.face("nile"), Face::Linked(600014432298598400));
+    assert_eq!(
+        a.face("bendn"),
+        Face::Made(hash("+41521zhHB8321xAbXYedw=="))
+    );
+    a.link("+41521zhHB8321xAbXYedw==", 696196765564534825)
+        .unwrap();
+    assert_eq!(a.face("bendn"), Face::Linked(696196765564534825));
+    assert_eq!(a.unlink(696196765564534825).unwrap(), 1);
+    assert_eq!(a.unlink(696196765564534825).unwrap(), 0);
+    assert_eq!(
+        a.face("bendn"),
+        Face::Made(hash("+41521zhHB8321xAbXYedw=="))
+    );
+    // ids cant change between builds
+    assert_eq!(hash("nile"), 0x08f501bab4b8c78b);
+
+    let i = identicon(hash("nile"));
+    assert_eq!(i, identicon(hash("nile")));
+    assert_ne!(i, identicon(hash("bendn")));
+    assert_eq!(i.rgb.len(), (80 * 80 * 3) as usize);
+    let side = CELLS * CELL;
+    let px = |x: u32, y: u32| &i.rgb[((y * side + x) * 3) as usize..][..3];
+    for y in 0..side {
+        for x in 0..side {
+            assert_eq!(px(x, y), px(side - 1 - x, y));
+        }
+    }
+}
+
+#[tokio::test]
+async fn failures() {
+    let a = Avatars::new(HashMap::from([(
+        "JjvYb8x7FWSV+zA0VsuHlw==".into(),
+        600014432298598400,
+    )]));
+    a.joined("nile", "JjvYb8x7FWSV+zA0VsuHlw==");
+    let asked = std::sync::atomic::AtomicU32::new(0);
+    let refuse = |_| async {
+        asked.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Err(anyhow::anyhow!("unknown user"))
+    };
+    // discord wont say, so the made one is used
+    let made = Avatars::made(hash("JjvYb8x7FWSV+zA0VsuHlw=="));
+    assert_eq!(a.url_with("nile", refuse).await, made);
+    // and it isnt asked again for a while
+    assert_eq!(a.url_with("nile", refuse).await, made);
+    assert_eq!(asked.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+    let b = Avatars::new(HashMap::from([(
+        "JjvYb8x7FWSV+zA0VsuHlw==".into(),
+        600014432298598400,
+    )]));
+    b.joined("nile", "JjvYb8x7FWSV+zA0VsuHlw==");
+    let face = |user| async move { Ok(format!("https://cdn.discordapp.com/avatars/{user}/a.png")) };
+    assert_eq!(
+        b.url_with("nile", face).await.as_deref(),
+        Some("https://cdn.discordapp.com/avatars/600014432298598400/a.png")
+    );
+    // remembered, so discord refusing later doesnt matter yet
+    assert_eq!(
+        b.url_with("nile", refuse).await.as_deref(),
+        Some("https://cdn.discordapp.com/avatars/600014432298598400/a.png")
+    );
+    assert_eq!(asked.load(std::sync::atomic::Ordering::Relaxed), 1);
+}
@@ -1,4 +1,4 @@
-use super::{return_next, send_ctx, Context, Result};
+use super::{return_next, Context, Result};
 use crate::bot::player::{self, Players};
 
 #[poise::command(
@@ -14,13 +14,13 @@ pub async fn add(
     #[description = "The player to make admin"]
     #[autocomplete = "player::autocomplete"]
     player: String,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
 ) -> Result<()> {
-    let player = Players::find(&ctx.data().stdin, player)
-        .await
-        .unwrap()
-        .unwrap();
-    send_ctx!(ctx, "admin add {}", player.uuid)?;
-    return_next!(ctx)
+    let s = super::server(ctx, server.as_deref())?;
+    let player = Players::get(&s, player).await?;
+    return_next!(ctx, s, "admin add {}", player.uuid)
 }
 
 #[poise::command(
@@ -36,11 +36,11 @@ pub async fn remove(
     #[description = "The player to remove admin status from"]
     #[autocomplete = "player::autocomplete"]
     player: String,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
 ) -> Result<()> {
-    let player = Players::find(&ctx.data().stdin, player)
-        .await
-        .unwrap()
-        .unwrap();
-    send_ctx!(ctx, "admin remove {}", player.uuid)?;
-    return_next!(ctx)
+    let s = super::server(ctx, server.as_deref())?;
+    let player = Players::get(&s, player).await?;
+    return_next!(ctx, s, "admin remove {}", player.uuid)
 }
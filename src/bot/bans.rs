@@ -1,4 +1,4 @@
-use super::{return_next, send_ctx, Context, Result};
+use super::{return_next, send, Context, Result};
 use crate::bot::player::{self, Players};
 
 #[poise::command(
@@ -14,14 +14,15 @@ pub async fn add(
     #[description = "player to ban"]
     #[autocomplete = "player::autocomplete"]
     player: String,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
 ) -> Result<()> {
     let _ = ctx.defer().await;
-    let player = Players::find(&ctx.data().stdin, player)
-        .await
-        .unwrap()
-        .unwrap();
-    send_ctx!(ctx, "ban ip {}", player.ip)?;
-    send_ctx!(ctx, "ban id {}", player.uuid)?;
+    let s = super::server(ctx, server.as_deref())?;
+    let player = Players::get(&s, player).await?;
+    send!(s.console, "ban ip {}", player.ip)?;
+    send!(s.console, "ban id {}", player.uuid)?;
     ctx.say(format!("banned {}", player.name)).await?;
     Ok(())
 }
@@ -38,13 +39,14 @@ pub async fn kick(
     #[description = "player to kick"]
     #[autocomplete = "player::autocomplete"]
     player: String,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
 ) -> Result<()> {
     let _ = ctx.defer().await;
-    let player = Players::find(&ctx.data().stdin, player)
-        .await
-        .unwrap()
-        .unwrap();
-    send_ctx!(ctx, "kick {}", player.uuid)?; // FIXME
+    let s = super::server(ctx, server.as_deref())?;
+    let player = Players::get(&s, player).await?;
+    send!(s.console, "kick {}", player.uuid)?; // FIXME
     ctx.say(format!("kicked {}", player.name)).await?;
     Ok(())
 }
@@ -61,18 +63,24 @@ pub async fn add_raw(
     ctx: Context<'_>,
     #[description = "uuid of player to ban"] uuid: Option<String>,
     #[description = "ip address of player to ban"] ip: Option<String>,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
 ) -> Result<()> {
     let _ = ctx.defer().await;
+    let s = super::server(ctx, server.as_deref())?;
     if uuid.is_none() && ip.is_none() {
         anyhow::bail!("what are you banning? yourself?")
     }
+    let mut out = vec![];
     if let Some(uuid) = uuid {
-        send_ctx!(ctx, "ban id {}", uuid)?;
+        out.push(s.console.request(format!("ban id {uuid}")).await?);
     }
     if let Some(ip) = ip {
-        send_ctx!(ctx, "ban ip {}", ip)?;
+        out.push(s.console.request(format!("ban ip {ip}")).await?);
     }
-    return_next!(ctx)
+    ctx.say(out.join("\n")).await?;
+    Ok(())
 }
 
 #[poise::command(
@@ -88,10 +96,13 @@ pub async fn remove(
     #[description = "Player id/ip"]
     #[rename = "ip_or_id"]
     player: String,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
 ) -> Result<()> {
     let _ = ctx.defer().await;
-    send_ctx!(ctx, "unban {}", player)?;
-    return_next!(ctx)
+    let s = super::server(ctx, server.as_deref())?;
+    return_next!(ctx, s, "unban {}", player)
 }
 
 // TODO: listbans
@@ -1,4 +1,4 @@
-use super::{return_next, send_ctx, Context, Result};
+use super::{return_next, Context, Result};
 use convert_case::{Case, Casing};
 use futures_util::StreamExt;
 
@@ -56,9 +56,12 @@ pub async fn set(
     #[description = "setting to change"]
     setting: String,
     #[description = "the value"] config: String,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
 ) -> Result<()> {
+    let s = super::server(ctx, server.as_deref())?;
     let setting = setting.from_case(Case::Lower).to_case(Case::Camel);
-    send_ctx!(ctx, "config {setting} {config}")?;
-    return_next!(ctx)
+    return_next!(ctx, s, "config {setting} {config}")
 }
 // TODO: config::list
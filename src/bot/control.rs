@@ -0,0 +1,160 @@
+use super::{Context, Result, DISABLED, FAIL, SUCCESS};
+use crate::server::State;
+use crate::supervisor::{Run, Supervisor};
+use poise::serenity_prelude::*;
+use std::sync::Arc;
+
+fn supervisor(s: &State) -> Result<Arc<Supervisor>> {
+    s.supervisor
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("{} isnt run by the panel (no `launch`)", s.name))
+}
+
+/// keep the end of `s`, up to `max` bytes
+fn last(mut s: String, max: usize) -> String {
+    if s.len() > max {
+        let mut at = s.len() - max;
+        while !s.is_char_boundary(at) {
+            at += 1;
+        }
+        s.drain(..at);
+    }
+    s
+}
+
+async fn reply(ctx: Context<'_>, s: &State, sup: &Supervisor, run: Run) -> Result<()> {
+    let tail = last(sup.tail(15).join("\n"), 1000);
+    let mut e = CreateEmbed::new()
+        .title(format!(
+            "{} {}",
+            s.name,
+            if run.running { "running" } else { "stopped" }
+        ))
+        .field("starts", run.starts.to_string(), true)
+        .color(match run.exit {
+            _ if run.running => SUCCESS,
+            Some(exit) if !exit.success() => FAIL,
+            _ => DISABLED,
+        });
+    if let Some(exit) = run.exit {
+        e = e.field("last exit", exit.to_string(), true);
+    }
+    if !tail.is_empty() {
+        e = e.description(format!("```\n{tail}\n```"));
+    }
+    poise::send_reply(ctx, poise::CreateReply::default().embed(e)).await?;
+    Ok(())
+}
+
+macro_rules! control {
+    ($name:ident, $doc:literal) => {
+        #[poise::command(
+            slash_command,
+            category = "Control",
+            required_permissions = "ADMINISTRATOR",
+            default_member_permissions = "ADMINISTRATOR"
+        )]
+        #[doc = $doc]
+        pub async fn $name(
+            ctx: Context<'_>,
+            #[description = "the server"]
+            #[autocomplete = "super::autocomplete_server"]
+            server: Option<String>,
+        ) -> Result<()> {
+            let s = super::server(ctx, server.as_deref())?;
+            let sup = supervisor(&s)?;
+            ctx.defer().await?;
+            let run = sup.$name().await;
+            reply(ctx, &s, &sup, run).await
+        }
+    };
+}
+
+control!(start, "start the server jar.");
+control!(stop, "stop the server (and keep it stopped).");
+control!(restart, "restart the server.");
+
+#[poise::command(
+    slash_command,
+    category = "Control",
+    rename = "server",
+    subcommands("start", "stop", "restart"),
+    required_permissions = "ADMINISTRATOR",
+    default_member_permissions = "ADMINISTRATOR"
+)]
+/// run the server process.
+pub async fn control(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    category = "Control",
+    subcommands("tail", "queues"),
+    required_permissions = "ADMINISTRATOR",
+    default_member_permissions = "ADMINISTRATOR"
+)]
+/// look at the server console.
+pub async fn console(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    category = "Control",
+    required_permissions = "ADMINISTRATOR",
+    default_member_permissions = "ADMINISTRATOR"
+)]
+/// the last lines of console output.
+pub async fn tail(
+    ctx: Context<'_>,
+    #[description = "how many lines (default 20)"]
+    #[min = 1]
+    #[max = 200]
+    n: Option<usize>,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
+) -> Result<()> {
+    let s = super::server(ctx, server.as_deref())?;
+    let lines = s.scrollback.tail(n.unwrap_or(20));
+    if lines.is_empty() {
+        poise::say_reply(ctx, "nothing yet").await?;
+        return Ok(());
+    }
+    let lines = lines.iter().map(ToString::to_string).collect::<Vec<_>>();
+    let text = last(lines.join("\n").replace("```", "`\u{200b}``"), 1900);
+    poise::say_reply(ctx, format!("```\n{text}\n```")).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    category = "Control",
+    required_permissions = "ADMINISTRATOR",
+    default_member_permissions = "ADMINISTRATOR"
+)]
+/// who is reading the console, and how far behind they are.
+pub async fn queues(
+    ctx: Context<'_>,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
+) -> Result<()> {
+    let s = super::server(ctx, server.as_deref())?;
+    let mut e = CreateEmbed::new()
+        .title(format!("{} console readers", s.name))
+        .color(SUCCESS);
+    for q in s.stdout.stats() {
+        e = e.field(
+            q.name,
+            format!(
+                "{}/{} queued ({})\n{} dropped, {} blocked",
+                q.queued, q.cap, q.overflow, q.dropped, q.blocked
+            ),
+            true,
+        );
+    }
+    poise::send_reply(ctx, poise::CreateReply::default().embed(e)).await?;
+    Ok(())
+}
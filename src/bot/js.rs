@@ -1,4 +1,4 @@
-use super::{return_next, send_ctx, Context, Result};
+use super::{return_next, Context, Result};
 use regex::Regex;
 use std::sync::LazyLock;
 
@@ -28,6 +28,6 @@ pub async fn run(
 ) -> Result<()> {
     let _ = ctx.channel_id().start_typing(&ctx.serenity_context().http);
     let script = parse_js(&script)?;
-    send_ctx!(ctx, "js {script}")?;
-    return_next!(ctx)
+    let s = super::server(ctx, None)?;
+    return_next!(ctx, s, "js {script}")
 }
@@ -1,41 +1,109 @@
-use super::{get_nextblock, send_ctx, Context};
+use super::Context;
+use crate::console::Error;
+use crate::server::State;
 use anyhow::Result;
 use regex::Regex;
 use std::sync::LazyLock;
 
-#[derive(poise::ChoiceParameter)]
+#[derive(Clone, Copy, poise::ChoiceParameter, serde_derive::Deserialize)]
 pub enum Team {
+    #[serde(rename = "survivors")]
     #[name = "survivors"]
     #[name_localized("it", "sopravvissuto")]
     #[name_localized("vi", "người sống sót")]
     Survivor,
+    #[serde(rename = "infected")]
     #[name = "infected"]
     #[name_localized("it", "infetto")]
     #[name_localized("vi", "bị lây nhiễm")]
     Infected,
 }
 
+static RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new("([0-9]+): <(.)([0-3])> ([^:]+): ([0-9]+) wins").unwrap());
+
+/// someone on the leaderboard
+#[derive(Clone, serde_derive::Serialize, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub place: u32,
+    /// like `A3`
+    pub rank: String,
+    pub name: String,
+    pub wins: u32,
+}
+
+/// the leaderboard, as the server says it (without the header)
+async fn raw(s: &State, team: Team) -> Result<String> {
+    let res = s
+        .console
+        .request(match team {
+            Team::Survivor => "lb surv",
+            Team::Infected => "lb inf",
+        })
+        .await?;
+    // skip the header
+    res.get(14..)
+        .map(str::to_owned)
+        .ok_or_else(|| Error::unparsable("lb", "too short").into())
+}
+
+/// the leaderboard, with discord emojis
+async fn leaderboard(s: &State, team: Team) -> Result<String> {
+    Ok(crate::emoji::mindustry::to_discord(
+        &RE.replace_all(&raw(s, team).await?, "<$2$3> $4: $5 wins"),
+    ))
+}
+
+pub async fn entries(s: &State, team: Team) -> Result<Vec<Entry>> {
+    RE.captures_iter(&raw(s, team).await?)
+        .map(|c| {
+            let num = |i: usize| -> Result<u32> {
+                c[i].parse()
+                    .map_err(|e| Error::unparsable("lb", format!("{:?}: {e}", &c[i])).into())
+            };
+            Ok(Entry {
+                place: num(1)?,
+                rank: format!("{}{}", &c[2], &c[3]),
+                name: c[4].to_owned(),
+                wins: num(5)?,
+            })
+        })
+        .collect()
+}
+
 #[poise::command(slash_command, category = "Info")]
 /// show leaderboard!
 pub async fn lb(
     c: Context<'_>,
     #[description = "the team to get the leaderboard of"] team: Team,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
 ) -> Result<()> {
-    send_ctx!(
-        c,
-        "lb {}",
-        match team {
-            Team::Survivor => "surv",
-            Team::Infected => "inf",
-        }
-    )
-    .unwrap();
-    static RE: LazyLock<Regex> =
-        LazyLock::new(|| Regex::new("[0-9]: <(.)([0-3])> ([^:]+): ([0-9]+) wins").unwrap());
-    c.reply(crate::emoji::mindustry::to_discord(&RE.replace_all(
-        &get_nextblock().await[14..],
-        "<$1$2> $3: $4 wins",
-    )))
-    .await?;
+    let s = super::server(c, server.as_deref())?;
+    c.reply(leaderboard(&s, team).await?).await?;
     Ok(())
 }
+
+#[tokio::test]
+async fn board() {
+    let fake = crate::fake::Fake::script(crate::fake::CONSOLE)
+        .spawn()
+        .await;
+    let (s, _stdout) = fake.server();
+    let board = leaderboard(&s, Team::Survivor).await.unwrap();
+    assert!(board.contains("bendn: 12 wins"));
+    assert!(board.contains("nile: 3 wins"));
+    let entries = entries(&s, Team::Survivor).await.unwrap();
+    assert_eq!(
+        entries[0],
+        Entry {
+            place: 1,
+            rank: "A3".into(),
+            name: "bendn".into(),
+            wins: 12
+        }
+    );
+    assert_eq!(entries.len(), 2);
+    assert_eq!(fake.heard(), ["lb surv", "lb surv"]);
+}
@@ -1,60 +1,70 @@
-use super::{get_nextblock, send, strip_colors, Context, Result, SUCCESS};
+use super::{server_of, strip_colors, Context, Result, SUCCESS};
+use crate::server::State;
+use crate::view::{Layers, Raw};
 use futures_util::StreamExt;
 use mindus::*;
-use oxipng::*;
 use poise::serenity_prelude::*;
-use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime};
-use tokio::sync::broadcast::{self, Sender};
 use tokio::sync::{Mutex, MutexGuard, OnceCell};
-pub struct Maps;
+pub struct Maps(OnceCell<Vec<String>>);
 impl Maps {
-    pub async fn find(map: &str, stdin: &broadcast::Sender<String>) -> usize {
-        Self::get_all(stdin)
-            .await
+    pub const fn new() -> Self {
+        Self(OnceCell::const_new())
+    }
+
+    pub async fn find(map: &str, s: &State) -> Result<usize> {
+        Self::get_all(s)
+            .await?
             .iter()
             .position(|r| r == map)
-            .unwrap()
+            .ok_or_else(|| anyhow::anyhow!("no map called {map}"))
     }
 
-    pub async fn get_all(stdin: &broadcast::Sender<String>) -> &Vec<String> {
-        static MAPS: OnceCell<Vec<String>> = OnceCell::const_new();
-        MAPS.get_or_init(|| async move {
-            send!(stdin, "maps").unwrap();
-            let res = get_nextblock().await;
-            let mut vec = vec![];
-            for line in res.lines() {
-                if let Some((_, name)) = line.split_once(':') {
-                    vec.push(strip_colors(name));
+    pub async fn get_all(s: &State) -> Result<&Vec<String>> {
+        s.maps
+            .0
+            .get_or_try_init(|| async move {
+                let res = s.console.request("maps").await?;
+                let mut vec = vec![];
+                for line in res.lines() {
+                    if let Some((_, name)) = line.split_once(':') {
+                        vec.push(strip_colors(name));
+                    }
                 }
-            }
-            vec
-        })
-        .await
+                Ok(vec)
+            })
+            .await
     }
 }
 
-pub async fn has(map: &str, ctx: &Context<'_>) -> bool {
-    Maps::get_all(&ctx.data().stdin)
-        .await
-        .iter()
-        .any(|x| map == x)
+pub async fn has(map: &str, s: &State) -> Result<bool> {
+    Ok(Maps::get_all(s).await?.iter().any(|x| map == x))
 }
 
 pub async fn autocomplete<'a>(
     ctx: Context<'a>,
     partial: &'a str,
 ) -> impl futures::Stream<Item = String> + 'a {
-    futures::stream::iter(Maps::get_all(&ctx.data().stdin).await)
+    let maps = Maps::get_all(&server_of(ctx))
+        .await
+        .cloned()
+        .unwrap_or_default();
+    futures::stream::iter(maps)
         .filter(move |name| futures::future::ready(name.starts_with(partial)))
-        .map(ToString::to_string)
 }
 
 #[poise::command(slash_command, category = "Info", rename = "maps")]
 /// lists the maps.
-pub async fn list(ctx: Context<'_>) -> Result<()> {
+pub async fn list(
+    ctx: Context<'_>,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
+) -> Result<()> {
     let _ = ctx.defer_or_broadcast().await;
-    let maps = Maps::get_all(&ctx.data().stdin).await;
+    let s = super::server(ctx, server.as_deref())?;
+    let maps = Maps::get_all(&s).await?;
     let mut e = CreateEmbed::default();
     for (k, v) in maps.iter().enumerate() {
         e = e.field((k + 1).to_string(), v, true);
@@ -70,87 +80,113 @@ pub struct RenderInfo {
     total: Duration,
     name: String,
 }
-pub static MAP_IMAGE: MapImage = MapImage(Mutex::const_new(vec![]), AtomicU64::new(0));
-pub struct MapImage(Mutex<Vec<u8>>, AtomicU64);
+/// how long a render is good for, in seconds
+pub const TTL: u64 = 70;
+
+/// a rendered map
+pub struct Image {
+    pub png: Vec<u8>,
+    /// the pixels, for other views. none before the first render
+    pub layers: Option<Arc<Layers>>,
+    /// when it was rendered, in unix seconds
+    pub at: u64,
+}
+
+/// draw a save, and its png. blocks.
+pub fn render(save: &[u8]) -> Result<((Vec<u8>, Layers), RenderInfo)> {
+    let then = Instant::now();
+    let mut m = data::map::MapReader::new(&mut data::DataRead::new(save))?;
+    m.header()?;
+    m.version()?;
+    let name = m.tags()?["mapname"].to_owned();
+    m.skip()?;
+    let render_took = Instant::now();
+    let (mut i, sz) = data::renderer::draw_map_single(&mut m)?;
+    // for `/view?units=false`
+    let bare = i.clone();
+    let bare = Raw {
+        width: bare.width(),
+        height: bare.height(),
+        rgb: bare.take_buffer().to_vec(),
+    };
+    data::renderer::draw_units(&mut m, i.as_mut(), sz)?;
+    let units = Raw {
+        width: i.width(),
+        height: i.height(),
+        rgb: i.take_buffer().to_vec(),
+    };
+    let render_took = render_took.elapsed();
+    let compression_took = Instant::now();
+    let png = crate::view::png(&units);
+    let compression_took = compression_took.elapsed();
+    let total = then.elapsed();
+    Ok((
+        (png, Layers { units, bare }),
+        RenderInfo {
+            render: render_took,
+            compression: compression_took,
+            name,
+            total,
+        },
+    ))
+}
+
+pub struct MapImage(Mutex<Image>);
 impl MapImage {
-    /// procure the map image.
+    pub const fn new() -> Self {
+        Self(Mutex::const_new(Image {
+            png: vec![],
+            layers: None,
+            at: 0,
+        }))
+    }
+
+    /// procure the map image. whoever asks during a render waits for it.
     pub async fn get(
         &self,
-        stdin: &Sender<String>,
+        s: &State,
         // returning a guard is questionable
-    ) -> Result<(MutexGuard<Vec<u8>>, Option<RenderInfo>)> {
+    ) -> Result<(MutexGuard<Image>, Option<RenderInfo>)> {
         // me in a million years when its 1901 and we never get a new render
         let now = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_secs();
-        Ok(
-            if self
-                .1
-                .fetch_update(Relaxed, Relaxed, |then| (now > then + 70).then_some(now))
-                .is_err()
-            {
-                (self.0.lock().await, None)
-            } else {
-                let o = savefile(stdin).await?;
-                let (i, info) = tokio::task::spawn_blocking(move || {
-                    let then = Instant::now();
-                    let mut m = data::map::MapReader::new(&mut data::DataRead::new(&o))?;
-                    m.header()?;
-                    m.version()?;
-                    let name = m.tags()?["mapname"].to_owned();
-                    m.skip()?;
-                    let render_took = Instant::now();
-                    let (mut i, sz) = data::renderer::draw_map_single(&mut m)?;
-                    data::renderer::draw_units(&mut m, i.as_mut(), sz)?;
-                    let render_took = render_took.elapsed();
-                    let compression_took = Instant::now();
-                    let i = RawImage::new(
-                        i.width(),
-                        i.height(),
-                        ColorType::RGB {
-                            transparent_color: None,
-                        },
-                        BitDepth::Eight,
-                        i.take_buffer().to_vec(),
-                    )
-                    .unwrap();
-                    let i = i
-                        .create_optimized_png(&oxipng::Options {
-                            filter: indexset! { RowFilter::None },
-                            bit_depth_reduction: false,
-                            color_type_reduction: false,
-                            palette_reduction: false,
-                            grayscale_reduction: false,
-                            ..oxipng::Options::from_preset(0)
-                        })
-                        .unwrap();
-                    let compression_took = compression_took.elapsed();
-                    let total = then.elapsed();
-                    anyhow::Ok((
-                        i,
-                        RenderInfo {
-                            render: render_took,
-                            compression: compression_took,
-                            name,
-                            total,
-                        },
-                    ))
-                })
-                .await??;
-                let mut lock = self.0.lock().await;
-                *lock = i;
-                (lock, Some(info))
-            },
-        )
+        let mut lock = self.0.lock().await;
+        if now <= lock.at + TTL {
+            return Ok((lock, None));
+        }
+        // a failed render leaves `at` alone, so the next ask tries again
+        let o = savefile(s).await?;
+        let (i, info) = tokio::task::spawn_blocking(move || render(&o)).await??;
+        crate::metrics::rendered(
+            &s.name,
+            [
+                ("render", info.render),
+                ("compression", info.compression),
+                ("total", info.total),
+            ],
+        );
+        *lock = Image {
+            png: i.0,
+            layers: Some(Arc::new(i.1)),
+            at: now,
+        };
+        Ok((lock, Some(info)))
     }
 }
 
 #[poise::command(slash_command, category = "Info")]
 /// look at the current game.
-pub async fn view(ctx: Context<'_>) -> Result<()> {
+pub async fn view(
+    ctx: Context<'_>,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
+) -> Result<()> {
     let _ = ctx.defer_or_broadcast().await;
-    let (i, info) = MAP_IMAGE.get(&ctx.data().stdin).await?;
+    let s = super::server(ctx, server.as_deref())?;
+    let (i, info) = s.map_image.get(&s).await?;
     let mut e = CreateEmbed::default();
     if let Some(RenderInfo {
         render,
@@ -173,17 +209,71 @@ pub async fn view(ctx: Context<'_>) -> Result<()> {
     poise::send_reply(
         ctx,
         poise::CreateReply::default()
-            .attachment(CreateAttachment::bytes(&**i, "0.png"))
+            .attachment(CreateAttachment::bytes(&*i.png, "0.png"))
             .embed(e),
     )
     .await?;
     Ok(())
 }
 
-pub async fn savefile(s: &Sender<String>) -> Result<Vec<u8>> {
-    send!(s, "save 0")?;
-    let _ = get_nextblock().await;
+#[poise::command(slash_command, category = "Info")]
+/// a timelapse of the game so far.
+pub async fn timelapse(
+    ctx: Context<'_>,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
+) -> Result<()> {
+    let s = super::server(ctx, server.as_deref())?;
+    let gif = s
+        .timelapse
+        .gif()
+        .ok_or_else(|| anyhow::anyhow!("not enough frames of this game yet"))?;
+    poise::send_reply(
+        ctx,
+        poise::CreateReply::default()
+            .attachment(CreateAttachment::bytes(gif, "timelapse.gif"))
+            .embed(
+                CreateEmbed::new()
+                    .attachment("timelapse.gif")
+                    .color(SUCCESS),
+            ),
+    )
+    .await?;
+    Ok(())
+}
+
+pub async fn savefile(s: &State) -> Result<Vec<u8>> {
+    s.console.request("save 0").await?;
+
+    // parsing the thing doesnt negate the need for a config entry sooo
+    Ok(std::fs::read(&s.save)?)
+}
+
+#[tokio::test]
+async fn maps() {
+    let fake = crate::fake::Fake::script(crate::fake::CONSOLE)
+        .spawn()
+        .await;
+    let (s, _stdout) = fake.server();
+    assert_eq!(
+        Maps::get_all(&s).await.unwrap(),
+        &["Ancient Caldera", "Frozen Forest", "plague"]
+    );
+    assert_eq!(Maps::find("plague", &s).await.unwrap(), 2);
+    assert!(Maps::find("nowhere", &s).await.is_err());
+}
 
-    // parsing the thing doesnt negate the need for a env var sooo
-    Ok(std::fs::read(std::env::var("SAVE_PATH").unwrap())?)
+#[tokio::test]
+async fn unrendered() {
+    let fake = crate::fake::Fake::script(crate::fake::CONSOLE)
+        .spawn()
+        .await;
+    let (s, _stdout) = fake.server();
+    // theres no save to render
+    assert!(s.map_image.get(&s).await.is_err());
+    assert_eq!(s.map_image.0.lock().await.at, 0);
+    // so its tried again, not skipped for a while
+    assert!(s.map_image.get(&s).await.is_err());
+    assert_eq!(fake.heard(), ["save 0", "save 0"]);
 }
@@ -1,17 +1,22 @@
 mod admin;
 mod bans;
 mod config;
+mod control;
 mod exec;
 mod js;
-mod lb;
+pub mod lb;
 pub mod maps;
-mod player;
-mod rules;
-mod status;
+pub mod player;
+mod relay;
+pub mod rules;
+mod snapshot;
+pub mod status;
 mod trace;
 mod voting;
 
 use crate::emoji::named::*;
+use crate::fanout::Overflow;
+use crate::server::{Servers, State};
 use crate::webhook::Webhook;
 use anyhow::Result;
 use maps::Maps;
@@ -22,12 +27,8 @@ use serenity::model::channel::Message;
 use std::fmt::Write;
 use std::fs::read_to_string;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::sync::LazyLock;
-use std::sync::{
-    atomic::{AtomicU8, Ordering},
-    Arc, OnceLock,
-};
-use tokio::sync::broadcast;
 
 pub async fn trusted(c: Context<'_>) -> Result<bool> {
     let c = c.author_member().await.ok_or(anyhow::anyhow!("dang"))?;
@@ -36,15 +37,60 @@ pub async fn trusted(c: Context<'_>) -> Result<bool> {
 
 #[derive(Debug)]
 pub struct Data {
-    stdin: broadcast::Sender<String>,
+    servers: Arc<Servers>,
     vote_data: voting::Votes,
 }
 
-static SKIPPING: OnceLock<(Arc<AtomicU8>, broadcast::Sender<String>)> = OnceLock::new();
+/// the server a command is for: the `server` argument if given,
+/// else the server relaying to this channel, else the first server.
+fn server(ctx: Context<'_>, name: Option<&str>) -> Result<Arc<State>> {
+    let servers = &ctx.data().servers;
+    match name {
+        Some(name) => servers
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("no server called {name}")),
+        None => Ok(servers
+            .by_channel(ctx.channel_id().get())
+            .unwrap_or(servers.first())
+            .clone()),
+    }
+}
+
+/// [`server`], for autocompletes, which have to dig the argument out of the interaction.
+fn server_of(ctx: Context<'_>) -> Arc<State> {
+    let name = match ctx {
+        poise::Context::Application(x) => {
+            x.args
+                .iter()
+                .find(|o| o.name == "server")
+                .and_then(|o| match o.value {
+                    ResolvedValue::String(x) => Some(x),
+                    _ => None,
+                })
+        }
+        poise::Context::Prefix(_) => None,
+    };
+    server(ctx, name).unwrap_or_else(|_| ctx.data().servers.first().clone())
+}
+
+pub async fn autocomplete_server<'a>(
+    ctx: Context<'a>,
+    partial: &'a str,
+) -> impl futures::Stream<Item = String> + 'a {
+    futures::stream::iter(
+        ctx.data()
+            .servers
+            .iter()
+            .map(|s| s.name.clone())
+            .filter(|name| name.starts_with(partial))
+            .collect::<Vec<_>>(),
+    )
+}
 
 macro_rules! send {
     ($e:expr, $fmt:literal $(, $args:expr)* $(,)?) => {
-        $e.send(format!($fmt $(, $args)*))
+        $e.send(format!($fmt $(, $args)*)).await
     };
 }
 use send;
@@ -55,13 +101,6 @@ macro_rules! repl {
 }
 use repl;
 
-macro_rules! send_ctx {
-    ($e:expr,$fmt:literal $(, $args:expr)* $(,)?) => {
-        $e.data().stdin.send(format!($fmt $(, $args)*))
-    };
-}
-use send_ctx;
-
 pub const SOURCE_GUILD: u64 = 1003092764919091282;
 pub mod emojis {
     use super::SOURCE_GUILD;
@@ -99,11 +138,11 @@ const PFX: &str = ">";
 #[cfg(debug_assertions)]
 const GUILD: u64 = SOURCE_GUILD;
 #[cfg(debug_assertions)]
-const CHANNEL: u64 = 1003092765581787279;
+pub const CHANNEL: u64 = 1003092765581787279;
 #[cfg(not(debug_assertions))]
 const GUILD: u64 = 1110086242177142854;
 #[cfg(not(debug_assertions))]
-const CHANNEL: u64 = 1142100900442296441;
+pub const CHANNEL: u64 = 1142100900442296441;
 
 const SUCCESS: (u8, u8, u8) = (34, 139, 34);
 const FAIL: (u8, u8, u8) = (255, 69, 0);
@@ -171,30 +210,99 @@ pub async fn discord_to_mindustry(m: &Message, c: &serenity::client::Context) ->
     EMOJI.replace(&new, ":$1:").into_owned()
 }
 
-pub async fn say(c: &serenity::client::Context, m: &Message, d: &Data) -> Result<()> {
-    let n = m
-        .author_nick(&c.http)
+async fn nick(c: &serenity::client::Context, m: &Message) -> String {
+    m.author_nick(&c.http)
+        .await
+        .unwrap_or_else(|| m.author.name.replace("ggfenguin", "eris"))
+}
+
+pub async fn say(c: &serenity::client::Context, m: &Message, s: &State) -> Result<()> {
+    let n = nick(c, m).await;
+    if say_as(s, &n, &discord_to_mindustry(m, c).await)
         .await
-        .unwrap_or_else(|| m.author.name.replace("ggfenguin", "eris"));
-    for l in discord_to_mindustry(m, c).await.lines() {
-        if send!(
-            d.stdin,
-            "say [royal] [coral][[[scarlet]{n}[coral]]:[white] {l}"
-        )
         .is_err()
-        {
-            return Ok(());
-        };
+    {
+        return Ok(());
+    }
+    m.react(&c.http, emojis::get!(ARROW)).await?;
+    Ok(())
+}
+
+/// from the staff channel, to only the admins in game.
+pub async fn admin_say(c: &serenity::client::Context, m: &Message, s: &State) -> Result<()> {
+    let n = nick(c, m).await;
+    if admin_say_as(s, &n, &discord_to_mindustry(m, c).await)
+        .await
+        .is_err()
+    {
+        return Ok(());
     }
     m.react(&c.http, emojis::get!(ARROW)).await?;
     Ok(())
 }
 
+/// say something to only the admins in game, as `n`.
+pub async fn admin_say_as(s: &State, n: &str, text: &str) -> Result<()> {
+    for l in text.lines() {
+        // theres no console command for it
+        let line = serde_json::to_string(&format!(
+            "[#ff4444]<A>[coral][[[scarlet]{n}[coral]]:[white] {l}"
+        ))?;
+        send!(
+            s.console,
+            "js Groups.player.each(p => {{ if (p.admin) p.sendMessage({line}) }})"
+        )?;
+    }
+    Ok(())
+}
+
+/// say something in game, as `n` (from discord, or the web).
+pub async fn say_as(s: &State, n: &str, text: &str) -> Result<()> {
+    for l in text.lines() {
+        send!(
+            s.console,
+            "say [royal] [coral][[[scarlet]{n}[coral]]:[white] {l}"
+        )?;
+    }
+    Ok(())
+}
+
 pub struct Bot;
 impl Bot {
-    pub async fn spawn(stdout: broadcast::Receiver<String>, stdin: broadcast::Sender<String>) {
+    pub async fn spawn(servers: Arc<Servers>) {
         println!("bot startup");
+        let cap = std::env::var("RELAY_QUEUE").map_or(1024, |x| {
+            x.parse().expect("RELAY_QUEUE should be a number of blocks")
+        });
+        let overflow = std::env::var("RELAY_OVERFLOW")
+            .map_or(Overflow::DropOldest, |x| x.parse().expect("RELAY_OVERFLOW"));
         let tok = std::env::var("TOKEN").unwrap_or(read_to_string("token").expect("wher token"));
+        for s in servers.iter() {
+            let s = s.clone();
+            let tok = tok.clone();
+            tokio::spawn(async move {
+                // logged in, for the avatars of linked players
+                let http = Http::new(&tok);
+                let wh = Webhook::new(&http, &s.webhook).await;
+                let staff = match &s.staff {
+                    Some(staff) => Some(Webhook::new(&http, &staff.webhook).await),
+                    None => None,
+                };
+                let relay = async {
+                    loop {
+                        let stdout = s.stdout.subscribe("relay", cap, overflow);
+                        wh.link(stdout, &s, staff.as_ref()).await;
+                        println!("{}: relay fell behind; resubscribing", s.name);
+                    }
+                };
+                // sending happens alongside, so a slow discord only backs up the queue
+                tokio::join!(relay, wh.drain(), async {
+                    if let Some(staff) = &staff {
+                        staff.drain().await;
+                    }
+                });
+            });
+        }
         let f = poise::Framework::<Data, anyhow::Error>::builder()
             .options(poise::FrameworkOptions {
                 commands: vec![
@@ -208,9 +316,12 @@ impl Bot {
                     js::run(),
                     maps::list(),
                     maps::view(),
+                    maps::timelapse(),
+                    snapshot::snapshot(),
                     player::list(),
                     status::command(),
                     config::set(),
+                    relay::relay(),
                     voting::create(),
                     voting::fixall(),
                     voting::list(),
@@ -220,6 +331,8 @@ impl Bot {
                     trace::trace(),
                     lb::lb(),
                     exec::exec(),
+                    control::control(),
+                    control::console(),
                     start(),
                     end(),
                     help(),
@@ -238,8 +351,11 @@ impl Bot {
                                 {
                                     return Ok(());
                                 }
-                                if new_message.channel_id == CHANNEL {
-                                    say(c, new_message, d).await?;
+                                let channel = new_message.channel_id.get();
+                                if let Some(s) = d.servers.by_channel(channel) {
+                                    say(c, new_message, s).await?;
+                                } else if let Some(s) = d.servers.by_staff_channel(channel) {
+                                    admin_say(c, new_message, s).await?;
                                 }
                             }
                             _ => {}
@@ -247,6 +363,9 @@ impl Bot {
                         Ok(())
                     })
                 },
+                pre_command: |ctx| {
+                    Box::pin(async move { crate::metrics::invoked(&ctx.command().qualified_name) })
+                },
                 on_error: |e| Box::pin(on_error(e)),
                 prefix_options: poise::PrefixFrameworkOptions {
                     edit_tracker: Some(Arc::new(poise::EditTracker::for_timespan(
@@ -262,21 +381,13 @@ impl Bot {
                     poise::builtins::register_globally(ctx, &framework.options().commands).await?;
                     println!("registered");
                     Ok(Data {
-                        stdin,
+                        servers,
                         vote_data: voting::Votes::new(vec![]),
                     })
                     // todo: voting::fixall() auto
                 })
             })
             .build();
-        tokio::spawn(async move {
-            let http = Http::new("");
-            let wh = std::env::var("WEBHOOK")
-                .unwrap_or(read_to_string("webhook").expect("wher webhook"));
-            let mut wh = Webhook::new(&http, &wh).await;
-            SKIPPING.get_or_init(|| (wh.skip.clone(), wh.skipped.clone()));
-            wh.link(stdout).await;
-        });
         ClientBuilder::new(tok, GatewayIntents::all())
             .framework(f)
             .await
@@ -293,6 +404,28 @@ async fn on_error(error: poise::FrameworkError<'_, Data, anyhow::Error>) {
     use poise::FrameworkError::Command;
     match error {
         Command { error, ctx, .. } => {
+            // failed checks and permissions are people, not the command
+            crate::metrics::failed(&ctx.command().qualified_name);
+            // the console misbehaving isnt a bug, so no backtrace
+            if let Some(e) = error.downcast_ref::<crate::console::Error>() {
+                use crate::console::Error::*;
+                let title = match e {
+                    Timeout { .. } => "server didnt answer",
+                    Disconnected { .. } => "server unreachable",
+                    Unparsable { .. } => "server said something weird",
+                };
+                let _ = poise::send_reply(
+                    ctx,
+                    poise::CreateReply::default().embed(
+                        CreateEmbed::new()
+                            .title(title)
+                            .description(e.to_string())
+                            .color(FAIL),
+                    ),
+                )
+                .await;
+                return;
+            }
             let mut msg;
             {
                 let mut chain = error.chain();
@@ -344,13 +477,14 @@ async fn raw(
     #[rest]
     cmd: String,
 ) -> Result<()> {
-    send_ctx!(ctx, "{cmd}")?;
+    send!(server(ctx, None)?.console, "{cmd}")?;
     Ok(())
 }
 
+/// send a command, and reply with its output
 macro_rules! return_next {
-    ($ctx:expr) => {{
-        let line = $crate::bot::get_nextblock().await;
+    ($ctx:expr, $s:expr, $fmt:literal $(, $args:expr)* $(,)?) => {{
+        let line = $s.console.request(format!($fmt $(, $args)*)).await?;
         $ctx.send(poise::CreateReply::default().content(line))
             .await?;
         return Ok(());
@@ -358,18 +492,6 @@ macro_rules! return_next {
 }
 use return_next;
 
-async fn get_nextblock() -> String {
-    let (skip_count, skip_send) = SKIPPING.get().unwrap();
-
-    skip_count.fetch_add(1, Ordering::Relaxed);
-
-    skip_send
-        .subscribe()
-        .recv()
-        .await
-        .unwrap_or("._?".to_string())
-}
-
 pub fn strip_colors(from: &str) -> String {
     let mut result = String::new();
     result.reserve(from.len());
@@ -398,9 +520,12 @@ pub async fn start(
     #[description = "the map"]
     #[autocomplete = "maps::autocomplete"]
     map: String,
+    #[description = "the server"]
+    #[autocomplete = "crate::bot::autocomplete_server"]
+    server: Option<String>,
 ) -> Result<()> {
-    send_ctx!(ctx, "host {}", Maps::find(&map, &ctx.data().stdin).await)?;
-    return_next!(ctx)
+    let s = self::server(ctx, server.as_deref())?;
+    return_next!(ctx, s, "host {}", Maps::find(&map, &s).await?)
 }
 
 #[poise::command(slash_command, category = "Control", check = "trusted")]
@@ -410,17 +535,16 @@ pub async fn end(
     #[description = "the map to go to"]
     #[autocomplete = "maps::autocomplete"]
     map: String,
+    #[description = "the server"]
+    #[autocomplete = "crate::bot::autocomplete_server"]
+    server: Option<String>,
 ) -> Result<()> {
-    if !maps::has(&map, &ctx).await {
+    let s = self::server(ctx, server.as_deref())?;
+    if !maps::has(&map, &s).await? {
         repl!(ctx, "{CANCEL} pls pick one of the maps.")?;
         return Ok(());
     }
-    send_ctx!(
-        ctx,
-        "gameover {}",
-        Maps::find(&map, &ctx.data().stdin).await
-    )?;
-    return_next!(ctx)
+    return_next!(ctx, s, "gameover {}", Maps::find(&map, &s).await?)
 }
 
 #[poise::command(prefix_command, slash_command, track_edits, category = "Info")]
@@ -443,3 +567,23 @@ pub async fn help(
 
     Ok(())
 }
+
+#[tokio::test]
+async fn staff() {
+    let fake = crate::fake::Fake::script(crate::fake::CONSOLE)
+        .spawn()
+        .await;
+    let (s, _stdout) = fake.server();
+    admin_say_as(&s, "nile", "is \"proto\" griefing?")
+        .await
+        .unwrap();
+    // anything after means it got there
+    s.console.request("status").await.unwrap();
+    assert_eq!(
+        fake.heard(),
+        [
+            r#"js Groups.player.each(p => { if (p.admin) p.sendMessage("[#ff4444]<A>[coral][[[scarlet]nile[coral]]:[white] is \"proto\" griefing?") })"#,
+            "status"
+        ]
+    );
+}
@@ -1,4 +1,6 @@
-use super::{get_nextblock, send, strip_colors, Context, FAIL, SUCCESS};
+use super::{server_of, strip_colors, Context, FAIL, SUCCESS};
+use crate::console::Error;
+use crate::server::State;
 use anyhow::Result;
 use futures_util::StreamExt;
 use itertools::Itertools;
@@ -6,7 +8,7 @@ use poise::serenity_prelude::*;
 use std::net::Ipv4Addr;
 use std::str::FromStr;
 use std::time::Instant;
-use tokio::sync::{broadcast, MappedMutexGuard, Mutex, MutexGuard};
+use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard};
 
 #[derive(Clone, Debug)]
 pub struct Player {
@@ -16,43 +18,44 @@ pub struct Player {
     pub ip: Ipv4Addr,
 }
 
-static PLAYERS: Mutex<(Vec<Player>, Option<Instant>)> = Mutex::const_new((vec![], None));
-
-async fn update(
-    stdin: &broadcast::Sender<String>,
-) -> Result<MutexGuard<(Vec<Player>, Option<Instant>)>> {
-    let mut lock = PLAYERS.lock().await;
+async fn update(s: &State) -> Result<MutexGuard<(Vec<Player>, Option<Instant>)>> {
+    let mut lock = s.players.0.lock().await;
     if lock.1.is_none() || lock.1.unwrap().elapsed().as_millis() > 500 {
-        lock.0 = get_players(stdin).await?;
+        lock.0 = get_players(s).await?;
         lock.1 = Some(Instant::now());
     }
     Ok(lock)
 }
-pub struct Players {}
+pub struct Players(Mutex<(Vec<Player>, Option<Instant>)>);
 impl Players {
-    pub async fn get_all(
-        stdin: &broadcast::Sender<String>,
-    ) -> Result<MappedMutexGuard<Vec<Player>>> {
+    pub const fn new() -> Self {
+        Self(Mutex::const_new((vec![], None)))
+    }
+
+    pub async fn get_all(s: &State) -> Result<MappedMutexGuard<Vec<Player>>> {
         {
-            Ok(MutexGuard::map(update(stdin).await?, |(p, _)| p))
+            Ok(MutexGuard::map(update(s).await?, |(p, _)| p))
         }
     }
 
-    pub async fn find(
-        stdin: &broadcast::Sender<String>,
-        name: String,
-    ) -> Result<Option<MappedMutexGuard<Player>>> {
-        Ok(MutexGuard::try_map(update(stdin).await?, |(p, _)| {
+    pub async fn find(s: &State, name: String) -> Result<Option<MappedMutexGuard<Player>>> {
+        Ok(MutexGuard::try_map(update(s).await?, |(p, _)| {
             p.iter_mut().find(|x| x.name == name)
         })
         .ok())
     }
+
+    /// [`find`](Self::find), with not being online as an error
+    pub async fn get(s: &State, name: String) -> Result<MappedMutexGuard<Player>> {
+        Self::find(s, name.clone())
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("{name} isnt online"))
+    }
 }
 
-async fn get_players(stdin: &broadcast::Sender<String>) -> Result<Vec<Player>> {
+async fn get_players(s: &State) -> Result<Vec<Player>> {
     let mut players = vec![];
-    send!(stdin, "players")?;
-    let recv = get_nextblock().await;
+    let recv = s.console.request("players").await?;
     for line in recv.lines() {
         if line.starts_with("No") {
             break;
@@ -65,7 +68,8 @@ async fn get_players(stdin: &broadcast::Sender<String>) -> Result<Vec<Player>> {
                     admin: admin == "[A]",
                     name: strip_colors(name),
                     uuid: uuid.to_owned(),
-                    ip: Ipv4Addr::from_str(ip).unwrap(),
+                    ip: Ipv4Addr::from_str(ip)
+                        .map_err(|e| Error::unparsable("players", format!("{ip:?}: {e}")))?,
                 });
             }
         }
@@ -77,7 +81,10 @@ pub async fn autocomplete<'a>(
     ctx: Context<'a>,
     partial: &'a str,
 ) -> impl futures::Stream<Item = String> + 'a {
-    let x = Players::get_all(&ctx.data().stdin).await.unwrap().clone();
+    let x = Players::get_all(&server_of(ctx))
+        .await
+        .map(|x| x.clone())
+        .unwrap_or_default();
     futures::stream::iter(x)
         .filter(move |p| futures::future::ready(p.name.starts_with(partial)))
         .map(|p| p.name)
@@ -85,9 +92,15 @@ pub async fn autocomplete<'a>(
 
 #[poise::command(slash_command, category = "Info", rename = "players")]
 /// lists the currently online players.
-pub async fn list(ctx: Context<'_>) -> Result<()> {
+pub async fn list(
+    ctx: Context<'_>,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
+) -> Result<()> {
     let _ = ctx.defer().await;
-    let players = Players::get_all(&ctx.data().stdin).await.unwrap().clone();
+    let s = super::server(ctx, server.as_deref())?;
+    let players = Players::get_all(&s).await?.clone();
     poise::send_reply(
         ctx,
         poise::CreateReply::default().embed(if players.is_empty() {
@@ -109,3 +122,22 @@ pub async fn list(ctx: Context<'_>) -> Result<()> {
     .await?;
     Ok(())
 }
+
+#[tokio::test]
+async fn players() {
+    let fake = crate::fake::Fake::script(crate::fake::CONSOLE)
+        .spawn()
+        .await;
+    let (s, _stdout) = fake.server();
+    let players = Players::get_all(&s).await.unwrap().clone();
+    assert_eq!(
+        players.iter().map(|p| &*p.name).collect::<Vec<_>>(),
+        ["bendn", "nile"]
+    );
+    assert!(players[0].admin && !players[1].admin);
+    assert_eq!(players[1].ip, Ipv4Addr::new(10, 0, 0, 2));
+    // cached for a bit
+    assert!(Players::find(&s, "nile".into()).await.unwrap().is_some());
+    assert!(Players::get(&s, "proto".into()).await.is_err());
+    assert_eq!(fake.heard(), ["players"]);
+}
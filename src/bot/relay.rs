@@ -0,0 +1,188 @@
+use super::player::{self, Players};
+use super::{Context, Result, SUCCESS};
+use crate::avatars::AVATARS;
+use crate::mentions::{Rule, Target, MENTIONS};
+use poise::serenity_prelude::*;
+
+#[poise::command(
+    slash_command,
+    category = "Configuration",
+    subcommands("mentions", "link", "unlink"),
+    required_permissions = "ADMINISTRATOR",
+    default_member_permissions = "ADMINISTRATOR"
+)]
+/// configure the chat relay.
+pub async fn relay(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    category = "Configuration",
+    required_permissions = "ADMINISTRATOR",
+    default_member_permissions = "ADMINISTRATOR"
+)]
+/// relay a players chat with a discord users avatar.
+pub async fn link(
+    ctx: Context<'_>,
+    #[description = "the player (theyll need to be online)"]
+    #[autocomplete = "player::autocomplete"]
+    player: String,
+    #[description = "whose avatar they get"] user: User,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
+) -> Result<()> {
+    let s = super::server(ctx, server.as_deref())?;
+    let found = Players::get(&s, player.clone()).await?;
+    AVATARS.link(&found.uuid, user.id.get())?;
+    AVATARS.joined(&found.name, &found.uuid);
+    poise::send_reply(
+        ctx,
+        poise::CreateReply::default()
+            .content(format!("{player} now has {}s avatar", user.mention()))
+            .allowed_mentions(CreateAllowedMentions::new()),
+    )
+    .await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    category = "Configuration",
+    required_permissions = "ADMINISTRATOR",
+    default_member_permissions = "ADMINISTRATOR"
+)]
+/// stop relaying players with a discord users avatar.
+pub async fn unlink(
+    ctx: Context<'_>,
+    #[description = "the discord user"] user: User,
+) -> Result<()> {
+    let n = AVATARS.unlink(user.id.get())?;
+    if n == 0 {
+        anyhow::bail!("nobody is linked to {}", user.name);
+    }
+    poise::say_reply(ctx, format!("unlinked {n} players from {}", user.name)).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    category = "Configuration",
+    subcommands("add", "remove", "list"),
+    required_permissions = "ADMINISTRATOR",
+    default_member_permissions = "ADMINISTRATOR"
+)]
+/// who gets pinged when players type things.
+pub async fn mentions(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    category = "Configuration",
+    required_permissions = "ADMINISTRATOR",
+    default_member_permissions = "ADMINISTRATOR"
+)]
+/// ping someone when a player says something.
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "what the player types (like `@mods`)"] trigger: String,
+    #[description = "the role to ping"] role: Option<Role>,
+    #[description = "the user to ping"] user: Option<User>,
+    #[description = "whether the trigger is a regex"] regex: Option<bool>,
+    #[description = "how long before the same player can ping again (default 1m)"] cooldown: Option<
+        String,
+    >,
+) -> Result<()> {
+    let target = match (role, user) {
+        (Some(r), None) => Target::Role(r.id.get()),
+        (None, Some(u)) => Target::User(u.id.get()),
+        _ => anyhow::bail!("give a role or a user"),
+    };
+    let cooldown = match cooldown {
+        Some(c) => parse_duration::parse(&c)
+            .map_err(|e| anyhow::anyhow!("{c:?} isnt a duration: {e}"))?
+            .as_secs(),
+        None => 60,
+    };
+    MENTIONS.add(Rule {
+        trigger: trigger.clone(),
+        regex: regex.unwrap_or(false),
+        target,
+        cooldown,
+    })?;
+    // dont ping them just for being added
+    poise::send_reply(
+        ctx,
+        poise::CreateReply::default()
+            .content(format!("`{trigger}` now pings {}", target.mention()))
+            .allowed_mentions(CreateAllowedMentions::new()),
+    )
+    .await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    category = "Configuration",
+    required_permissions = "ADMINISTRATOR",
+    default_member_permissions = "ADMINISTRATOR"
+)]
+/// stop a trigger pinging.
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "the trigger"]
+    #[autocomplete = "triggers"]
+    trigger: String,
+) -> Result<()> {
+    if !MENTIONS.remove(&trigger)? {
+        anyhow::bail!("nothing is triggered by `{trigger}`");
+    }
+    poise::say_reply(ctx, format!("`{trigger}` no longer pings")).await?;
+    Ok(())
+}
+
+async fn triggers<'a>(
+    _: Context<'a>,
+    partial: &'a str,
+) -> impl futures::Stream<Item = String> + 'a {
+    futures::stream::iter(
+        MENTIONS
+            .list()
+            .into_iter()
+            .map(|r| r.trigger)
+            .filter(|t| t.starts_with(partial))
+            .collect::<Vec<_>>(),
+    )
+}
+
+#[poise::command(
+    slash_command,
+    category = "Configuration",
+    required_permissions = "ADMINISTRATOR",
+    default_member_permissions = "ADMINISTRATOR"
+)]
+/// what pings who.
+pub async fn list(ctx: Context<'_>) -> Result<()> {
+    let mut e = CreateEmbed::new().title("mentions").color(SUCCESS);
+    for r in MENTIONS.list().into_iter().take(25) {
+        e = e.field(
+            if r.regex {
+                format!("/{}/", r.trigger)
+            } else {
+                r.trigger
+            },
+            format!("{} (every {}s)", r.target.mention(), r.cooldown),
+            true,
+        );
+    }
+    poise::send_reply(
+        ctx,
+        poise::CreateReply::default()
+            .embed(e)
+            .allowed_mentions(CreateAllowedMentions::new()),
+    )
+    .await?;
+    Ok(())
+}
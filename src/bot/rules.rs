@@ -1,10 +1,10 @@
 use super::{repl, send, Context, Result};
-use crate::bot::get_nextblock;
+use crate::console::Error;
 use crate::emoji::named::*;
+use crate::server::State;
 use futures_util::StreamExt;
 use poise::serenity_prelude::*;
 use tokio::sync::Mutex;
-use tokio::sync::{broadcast, OnceCell};
 
 macro_rules! val {
     ($($k:ident($v:ty)),+) => {
@@ -183,39 +183,42 @@ rules!(
 //     "buildSpeedMultiplier": f32 ["Multiplier for building speed."],
 // );
 
-pub async fn commit(stdin: &broadcast::Sender<String>) {
-    send!(
-        stdin,
-        "rules {}",
-        serde_json::to_string(&*rules(stdin).await).unwrap()
-    )
-    .unwrap();
+pub async fn commit(s: &State) -> Result<()> {
+    let rules = serde_json::to_string(&*rules(s).await?).unwrap();
+    send!(s.console, "rules {rules}")?;
+    Ok(())
 }
 
-pub async fn rules(stdin: &broadcast::Sender<String>) -> tokio::sync::MutexGuard<Rules> {
-    static RULES: OnceCell<Mutex<Rules>> = OnceCell::const_new();
-    RULES
-        .get_or_init(|| async move {
-            send!(stdin, "rules").unwrap();
-            let res = get_nextblock().await;
-            Mutex::new(serde_json::from_str(&res).unwrap())
+pub async fn rules(s: &State) -> Result<tokio::sync::MutexGuard<Rules>> {
+    Ok(s.rules
+        .get_or_try_init(|| async move {
+            let res = s.console.request("rules").await?;
+            serde_json::from_str(&res)
+                .map(Mutex::new)
+                .map_err(|e| Error::unparsable("rules", e))
         })
-        .await
+        .await?
         .lock()
-        .await
+        .await)
 }
 
 #[poise::command(slash_command, category = "Configuration", rename = "list_rules")]
 /// check them rules
-pub async fn list(ctx: Context<'_>) -> Result<()> {
+pub async fn list(
+    ctx: Context<'_>,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
+) -> Result<()> {
+    let s = super::server(ctx, server.as_deref())?;
     poise::send_reply(
         ctx,
         poise::CreateReply::default().embed(
             CreateEmbed::new()
                 .title("rules")
                 .fields(
-                    rules(&ctx.data().stdin)
-                        .await
+                    rules(&s)
+                        .await?
                         .reduce()
                         .map(|(a, b)| (a.to_string(), b.to_string(), true)),
                 )
@@ -249,9 +252,13 @@ pub async fn set(
     #[autocomplete = "autocomplete"]
     rule: String,
     #[description = "lol"] value: String,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
 ) -> Result<()> {
-    rules(&ctx.data().stdin).await.set(&rule, &value)?;
-    commit(&ctx.data().stdin).await;
+    let s = super::server(ctx, server.as_deref())?;
+    rules(&s).await?.set(&rule, &value)?;
+    commit(&s).await?;
     repl!(ctx, "{OK}")?;
     Ok(())
 }
@@ -269,13 +276,39 @@ pub async fn del(
     #[description = "rule"]
     #[autocomplete = "autocomplete"]
     rule: String,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
 ) -> Result<()> {
-    match rules(&ctx.data().stdin).await.delete(&rule) {
+    let s = super::server(ctx, server.as_deref())?;
+    match rules(&s).await?.delete(&rule) {
         Some(true) => repl!(ctx, "{OK} removed"),
         Some(false) => repl!(ctx, "{WARNING} rule existed, but already none"),
         None => repl!(ctx, "{CANCEL} invalid rule!"),
     }?;
-    commit(&ctx.data().stdin).await;
+    commit(&s).await?;
 
     Ok(())
 }
+
+#[tokio::test]
+async fn roundtrip() {
+    let fake = crate::fake::Fake::script(crate::fake::CONSOLE)
+        .spawn()
+        .await;
+    let (s, _stdout) = fake.server();
+    {
+        let mut r = rules(&s).await.unwrap();
+        assert_eq!(r.fire, Some(false));
+        assert_eq!(r.unit_cap, Some(48));
+        r.set("unit_cap", "64").unwrap();
+    }
+    commit(&s).await.unwrap();
+    // anything after the commit means the commit got there
+    s.console.request("status").await.unwrap();
+    let heard = fake.heard();
+    let sent = heard[1].strip_prefix("rules ").unwrap();
+    let sent: serde_json::Value = serde_json::from_str(sent).unwrap();
+    assert_eq!(sent["unitCap"], 64);
+    assert_eq!(sent["buildSpeedMultiplier"], 2.5);
+}
@@ -0,0 +1,63 @@
+use super::{Context, Result, SUCCESS};
+use poise::serenity_prelude::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `10m` (ago), or unix seconds
+fn when(at: &str, now: u64) -> Result<u64> {
+    if let Ok(at) = at.parse::<u64>()
+        && at > 1_000_000_000
+    {
+        return Ok(at);
+    }
+    let ago = parse_duration::parse(at.trim().trim_end_matches(" ago"))
+        .map_err(|e| anyhow::anyhow!("{at:?} isnt a time like `10m` or `1700000000`: {e}"))?;
+    Ok(now.saturating_sub(ago.as_secs()))
+}
+
+#[poise::command(slash_command, category = "Info", subcommands("view"))]
+/// look at old saves.
+pub async fn snapshot(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[poise::command(slash_command, category = "Info")]
+/// how the map looked before.
+pub async fn view(
+    ctx: Context<'_>,
+    #[description = "how long ago (like `10m`), or a unix timestamp"] at: String,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
+) -> Result<()> {
+    let s = super::server(ctx, server.as_deref())?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let at = when(&at, now)?;
+    let snapshot = s
+        .archive
+        .before(at)
+        .ok_or_else(|| anyhow::anyhow!("no snapshots of {} from before <t:{at}:f>", s.name))?;
+    let _ = ctx.defer_or_broadcast().await;
+    let png = s.archive.render(snapshot.at).await?;
+    poise::send_reply(
+        ctx,
+        poise::CreateReply::default()
+            .attachment(CreateAttachment::bytes(&**png, "0.png"))
+            .embed(
+                CreateEmbed::new()
+                    .title(format!("{}, <t:{}:R>", s.name, snapshot.at))
+                    .description(format!("<t:{}:f>", snapshot.at))
+                    .attachment("0.png")
+                    .color(SUCCESS),
+            ),
+    )
+    .await?;
+    Ok(())
+}
+
+#[test]
+fn times() {
+    assert_eq!(when("10m", 10_000).unwrap(), 9_400);
+    assert_eq!(when("1h ago", 10_000).unwrap(), 6_400);
+    assert_eq!(when("1700000000", 10_000).unwrap(), 1_700_000_000);
+    assert!(when("yesterday-ish", 10_000).is_err());
+}
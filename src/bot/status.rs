@@ -1,12 +1,11 @@
-use super::send_ctx;
-use super::{get_nextblock, Context, FAIL, SUCCESS};
+use super::{Context, FAIL, SUCCESS};
 use anyhow::Result;
 use itertools::Itertools;
 use poise::serenity_prelude::*;
 use std::str::FromStr;
-use tokio::time::{sleep, Duration};
 
-fn parse(line: &str) -> Option<(u32, u32, u32)> {
+/// `57 TPS / 274 MB / 7 PLAYERS` -> (tps, mb, players)
+pub fn parse(line: &str) -> Option<(u32, u32, u32)> {
     let mut v = vec![];
     for piece in line.split('/') {
         v.push(u32::from_str(piece.trim().split_once(' ')?.0).ok()?);
@@ -54,9 +53,14 @@ pub fn humanize_bytes<T: Into<Size>>(bytes: T) -> String {
 
 #[poise::command(slash_command, category = "Info", rename = "status")]
 /// server status.
-pub async fn command(ctx: Context<'_>) -> Result<()> {
+pub async fn command(
+    ctx: Context<'_>,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
+) -> Result<()> {
     let _ = ctx.defer_or_broadcast().await;
-    send_ctx!(ctx, "status")?;
+    let s = super::server(ctx, server.as_deref())?;
     macro_rules! fail {
         ($ctx:expr,$fail:expr) => {{
             poise::send_reply(
@@ -68,9 +72,8 @@ pub async fn command(ctx: Context<'_>) -> Result<()> {
             return Ok(());
         }};
     }
-    let block = tokio::select! {
-        block = get_nextblock() => block,
-        () = sleep(Duration::from_secs(5)) => fail!(ctx, FAIL),
+    let Ok(block) = s.console.request("status").await else {
+        fail!(ctx, FAIL);
     };
     let Some((tps, mem, pcount)) = parse(&block) else {
         fail!(ctx, FAIL);
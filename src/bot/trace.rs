@@ -1,4 +1,5 @@
-use super::{get_nextblock, Context, SUCCESS};
+use super::{Context, SUCCESS};
+use crate::console::Error;
 use crate::emoji::named::*;
 use anyhow::Result;
 use poise::serenity_prelude::*;
@@ -28,14 +29,18 @@ struct PlayerInfo {
 pub async fn trace(
     ctx: Context<'_>,
     #[autocomplete = "super::player::autocomplete"] player: String,
+    #[description = "the server"]
+    #[autocomplete = "super::autocomplete_server"]
+    server: Option<String>,
 ) -> Result<()> {
-    super::send_ctx!(ctx, "trace {player}").unwrap();
-    let res = get_nextblock().await;
+    let s = super::server(ctx, server.as_deref())?;
+    let res = s.console.request(format!("trace {player}")).await?;
     let info = res
         .lines()
         .filter(|x| !x.is_empty())
         .map(serde_json::from_str::<PlayerInfo>)
-        .map(Result::unwrap);
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::unparsable("trace", e))?;
     let authorized = match ctx {
         poise::Context::Application(x) => x
             .author_member()
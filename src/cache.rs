@@ -0,0 +1,175 @@
+//! conditional requests (`ETag`, `Last-Modified`, `304`) and gzip, for things that are
+//! expensive to make and big to send.
+use axum::{
+    http::{header::*, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use flate2::{write::GzEncoder, Compression};
+use std::future::Future;
+use std::io::Write;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// what the client needs to know to cache a response.
+pub struct Cached {
+    /// quoted, like `"1700000000"`
+    pub etag: String,
+    pub modified: SystemTime,
+    /// `Cache-Control`
+    pub control: String,
+}
+
+fn secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// whether the client takes gzip
+pub fn gzips(req: &HeaderMap) -> bool {
+    req.get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(','))
+        .any(|x| {
+            let mut x = x.split(';').map(str::trim);
+            x.next() == Some("gzip") && !x.any(|q| q == "q=0")
+        })
+}
+
+impl Cached {
+    /// whether the client already has it. `If-None-Match` wins over `If-Modified-Since`.
+    pub fn fresh(&self, req: &HeaderMap) -> bool {
+        if let Some(tags) = req.get(IF_NONE_MATCH).and_then(|x| x.to_str().ok()) {
+            // weak comparison, so gzipped responses count too
+            return tags
+                .split(',')
+                .map(|t| t.trim().trim_start_matches("W/"))
+                .any(|t| t == "*" || t == self.etag);
+        }
+        req.get(IF_MODIFIED_SINCE)
+            .and_then(|x| httpdate::parse_http_date(x.to_str().ok()?).ok())
+            .is_some_and(|since| secs(self.modified) <= secs(since))
+    }
+
+    /// `304` if the client has it, else `body`. with `gzip`, its compressed for clients that take it.
+    pub fn reply(
+        &self,
+        req: &HeaderMap,
+        kind: &'static str,
+        body: impl FnOnce() -> Vec<u8>,
+        gzip: bool,
+    ) -> Response {
+        let gzip = gzip && gzips(req);
+        let mut res = if self.fresh(req) {
+            StatusCode::NOT_MODIFIED.into_response()
+        } else {
+            let mut body = body();
+            if gzip {
+                let mut e = GzEncoder::new(vec![], Compression::fast());
+                e.write_all(&body).unwrap();
+                body = e.finish().unwrap();
+            }
+            let mut res = ([(CONTENT_TYPE, kind)], body).into_response();
+            if gzip {
+                res.headers_mut()
+                    .insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            }
+            res
+        };
+        let h = res.headers_mut();
+        let etag = if gzip {
+            format!("W/{}", self.etag)
+        } else {
+            self.etag.clone()
+        };
+        if let Ok(etag) = etag.parse() {
+            h.insert(ETAG, etag);
+        }
+        h.insert(
+            LAST_MODIFIED,
+            httpdate::fmt_http_date(self.modified).parse().unwrap(),
+        );
+        if let Ok(control) = self.control.parse() {
+            h.insert(CACHE_CONTROL, control);
+        }
+        if gzip {
+            h.insert(VARY, HeaderValue::from_static("accept-encoding"));
+        }
+        res
+    }
+}
+
+/// the last result of something slow, kept a while, so however many ask (and however often),
+/// its only done once per `ttl`. failures arent kept.
+pub struct Fresh<T> {
+    ttl: Duration,
+    last: tokio::sync::Mutex<Option<(Instant, T)>>,
+}
+
+impl<T: Clone> Fresh<T> {
+    pub const fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            last: tokio::sync::Mutex::const_new(None),
+        }
+    }
+
+    /// whats kept, or whatever `get` gets if its too old. askers meanwhile wait for it.
+    pub async fn get<E, F: Future<Output = Result<T, E>>>(
+        &self,
+        get: impl FnOnce() -> F,
+    ) -> Result<T, E> {
+        let mut last = self.last.lock().await;
+        if let Some((at, x)) = &*last
+            && at.elapsed() < self.ttl
+        {
+            return Ok(x.clone());
+        }
+        let x = get().await?;
+        *last = Some((Instant::now(), x.clone()));
+        Ok(x)
+    }
+}
+
+#[test]
+fn conditional() {
+    let cached = Cached {
+        etag: "\"1700000000\"".into(),
+        modified: UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000),
+        control: "public, max-age=70".into(),
+    };
+    let req = |k, v: &str| {
+        let mut h = HeaderMap::new();
+        h.insert(k, v.parse().unwrap());
+        h
+    };
+    assert!(!cached.fresh(&HeaderMap::new()));
+    assert!(cached.fresh(&req(IF_NONE_MATCH, "\"1\", W/\"1700000000\"")));
+    assert!(!cached.fresh(&req(IF_NONE_MATCH, "\"1699999999\"")));
+    assert!(cached.fresh(&req(IF_MODIFIED_SINCE, "Tue, 14 Nov 2023 22:13:20 GMT")));
+    assert!(!cached.fresh(&req(IF_MODIFIED_SINCE, "Tue, 14 Nov 2023 22:13:19 GMT")));
+
+    let res = cached.reply(
+        &req(IF_NONE_MATCH, "\"1700000000\""),
+        "image/png",
+        Vec::new,
+        false,
+    );
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(res.headers()[CACHE_CONTROL], "public, max-age=70");
+
+    assert!(gzips(&req(ACCEPT_ENCODING, "br, gzip;q=0.8")));
+    assert!(!gzips(&req(ACCEPT_ENCODING, "gzip;q=0, br")));
+    let res = cached.reply(&req(ACCEPT_ENCODING, "gzip"), "a", || vec![7; 1000], true);
+    assert_eq!(res.headers()[CONTENT_ENCODING], "gzip");
+    assert_eq!(res.headers()[ETAG], "W/\"1700000000\"");
+}
+
+#[tokio::test]
+async fn fresh() {
+    let f = Fresh::new(Duration::from_secs(60));
+    assert_eq!(f.get(|| async { Err::<u8, _>("nope") }).await, Err("nope"));
+    assert_eq!(f.get(|| async { Ok::<_, ()>(1) }).await, Ok(1));
+    assert_eq!(f.get(|| async { Ok::<_, ()>(2) }).await, Ok(1));
+    let f = Fresh::new(Duration::ZERO);
+    assert_eq!(f.get(|| async { Ok::<_, ()>(1) }).await, Ok(1));
+    assert_eq!(f.get(|| async { Ok::<_, ()>(2) }).await, Ok(2));
+}
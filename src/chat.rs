@@ -0,0 +1,166 @@
+//! the web chat bridge: recent chat, and a way to answer it without discord.
+use crate::auth::Session;
+use crate::events::Event;
+use crate::fanout::Overflow;
+use crate::server::{pick, unavailable, Servers};
+use crate::webhook::Message;
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::sse::{Event as Sse, KeepAlive},
+    response::IntoResponse,
+    Form,
+};
+use futures::StreamExt;
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// chat lines kept per server
+const KEEP: usize = 100;
+/// messages a user may send per [`PER`]
+const RATE: u32 = 5;
+const PER: Duration = Duration::from_secs(15);
+/// characters per message
+const LONGEST: usize = 200;
+
+/// recent chat, from the relay.
+pub struct History(Mutex<VecDeque<Event>>);
+
+impl History {
+    pub const fn new() -> Self {
+        Self(Mutex::new(VecDeque::new()))
+    }
+
+    /// keep it, if its chat
+    pub fn record(&self, e: &Event) {
+        if !matches!(e.message, Message::Chat { .. }) {
+            return;
+        }
+        let mut h = self.0.lock().unwrap();
+        if h.len() == KEEP {
+            h.pop_front();
+        }
+        h.push_back(e.clone());
+    }
+
+    /// oldest first
+    pub fn recent(&self) -> Vec<Event> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// user -> (messages, since)
+struct Limits(Mutex<HashMap<String, (u32, Instant)>>);
+
+impl Limits {
+    /// whether `user` may say something now
+    fn take(&self, user: &str, now: Instant) -> bool {
+        let mut limits = self.0.lock().unwrap();
+        limits.retain(|_, (_, since)| now.duration_since(*since) < PER);
+        let (n, _) = limits.entry(user.to_owned()).or_insert((0, now));
+        *n += 1;
+        *n <= RATE
+    }
+}
+
+static LIMITS: LazyLock<Limits> = LazyLock::new(|| Limits(Mutex::new(HashMap::new())));
+
+/// `WEB_CHAT_PREFIX`, before the users name in game, so nobody mistakes them for a player.
+static PREFIX: LazyLock<String> =
+    LazyLock::new(|| std::env::var("WEB_CHAT_PREFIX").unwrap_or_else(|_| "(web) ".into()));
+
+/// one line, without anything that could mess with the console
+fn clean(text: &str) -> Result<String, &'static str> {
+    let text = text
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect::<String>();
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("say something");
+    }
+    if text.chars().count() > LONGEST {
+        return Err("thats too long");
+    }
+    Ok(text.to_owned())
+}
+
+/// `/chat/feed/<server>`: recent chat, then chat as it happens, like `/events`.
+pub async fn feed(
+    axum::extract::State(servers): axum::extract::State<Arc<Servers>>,
+    server: Option<Path<String>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let s = pick(&servers, server)?;
+    let (recent, rx) = s
+        .events
+        .subscribe_with("chat", 256, Overflow::Disconnect, || s.chat.recent());
+    let live = futures::stream::unfold(rx, |mut rx| async move {
+        loop {
+            let e = rx.recv().await?;
+            if matches!(e.message, Message::Chat { .. }) {
+                return Some((e, rx));
+            }
+        }
+    });
+    let stream = futures::stream::iter(recent)
+        .chain(live)
+        .map(|e| Ok::<_, Infallible>(Sse::default().data(serde_json::to_string(&e).unwrap())));
+    Ok(axum::response::sse::Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[derive(serde_derive::Deserialize)]
+pub struct Said {
+    message: String,
+}
+
+/// `POST /chat/say/<server>`: say something in game, as whoever is logged in.
+pub async fn say(
+    axum::extract::State(servers): axum::extract::State<Arc<Servers>>,
+    server: Option<Path<String>>,
+    who: Session,
+    Form(said): Form<Said>,
+) -> Result<StatusCode, (StatusCode, &'static str)> {
+    let s = pick(&servers, server).map_err(|e| (e, "no such server"))?;
+    let text = clean(&said.message).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if !LIMITS.take(&who.name, Instant::now()) {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "slow down"));
+    }
+    println!("web: {} says {text:?} on {}", who.name, s.name);
+    crate::bot::say_as(&s, &format!("{}{}", *PREFIX, who.name), &text)
+        .await
+        .map_err(|e| (unavailable(e), "the server isnt listening"))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[test]
+fn chat() {
+    let h = History::new();
+    for i in 0..KEEP + 5 {
+        h.record(&Event::now(Message::Chat {
+            player: "nile".into(),
+            content: i.to_string(),
+        }));
+    }
+    h.record(&Event::now(Message::Join {
+        player: "nile".into(),
+        uuid: "JjvYb8x7FWSV+zA0VsuHlw==".into(),
+    }));
+    let recent = h.recent();
+    assert_eq!(recent.len(), KEEP);
+    assert!(matches!(&recent[0].message, Message::Chat { content, .. } if content == "5"));
+
+    assert_eq!(clean("  hi\nthere "), Ok("hi there".into()));
+    assert!(clean(" \n").is_err());
+    assert!(clean(&"a".repeat(LONGEST + 1)).is_err());
+
+    let limits = Limits(Mutex::new(HashMap::new()));
+    let now = Instant::now();
+    for _ in 0..RATE {
+        assert!(limits.take("nile", now));
+    }
+    assert!(!limits.take("nile", now));
+    assert!(limits.take("bendn", now));
+    assert!(limits.take("nile", now + PER));
+}
@@ -0,0 +1,390 @@
+use std::fmt;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex as SyncMutex,
+};
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot, watch, Mutex};
+use tokio::time::timeout;
+
+/// whether the console is there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Link {
+    Connected,
+    /// lost, and trying again
+    Reconnecting,
+    /// a few attempts failed; still trying, but dont hold your breath
+    Down,
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Connected => "connected",
+            Self::Reconnecting => "reconnecting",
+            Self::Down => "down",
+        })
+    }
+}
+
+/// why a console round trip didnt work out.
+#[derive(Debug)]
+pub enum Error {
+    /// no answer in time
+    Timeout { cmd: String, after: Duration },
+    /// the console isnt there, or went away before answering
+    Disconnected { link: Link, why: &'static str },
+    /// it answered, but not with anything we understand
+    Unparsable { cmd: String, why: String },
+}
+
+impl Error {
+    pub fn unparsable(cmd: impl Into<String>, why: impl fmt::Display) -> Self {
+        Self::Unparsable {
+            cmd: cmd.into(),
+            why: why.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout { cmd, after } => write!(f, "`{cmd}` got no answer within {after:?}"),
+            Self::Disconnected { link, why } => write!(f, "the console is {link}: {why}"),
+            Self::Unparsable { cmd, why } => write!(f, "couldnt make sense of `{cmd}`: {why}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// printed (with `js`) before a commands output
+const START: &str = "panel:start";
+/// and after it
+const END: &str = "panel:end";
+
+/// a command waiting for its output
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    tx: oneshot::Sender<String>,
+    /// whether everyone else sees the output too (for [`Console::send`])
+    keep: bool,
+}
+
+/// talks to the server console.
+///
+/// the console has no notion of which output belongs to which command, so commands are queued
+/// (only one is in flight at a time), and each is sent between two `js` marks. whats printed
+/// between them is its output, and is handed to whoever sent it.
+#[derive(Debug)]
+pub struct Console {
+    stdin: broadcast::Sender<String>,
+    /// held from sending a command until its output arrives
+    turn: Mutex<()>,
+    /// the command currently waiting for output
+    waiting: SyncMutex<Option<Waiter>>,
+    /// the output being collected, since its start mark
+    open: SyncMutex<Option<(u64, String)>>,
+    seq: AtomicU64,
+    link: watch::Sender<Link>,
+    /// how long commands wait for a lost console to come back. zero fails them right away.
+    queue: Duration,
+    /// how long a request waits for its answer
+    answer: Duration,
+}
+
+/// clears the waiting slot when a request is done (or dropped), so a late answer goes to the relay.
+struct Waiting<'a>(&'a Console, u64);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let mut waiting = self.0.waiting.lock().unwrap();
+        if waiting.as_ref().is_some_and(|w| w.id == self.1) {
+            *waiting = None;
+        }
+    }
+}
+
+impl Console {
+    pub fn new(stdin: broadcast::Sender<String>, queue: Duration, answer: Duration) -> Self {
+        Self {
+            stdin,
+            turn: Mutex::new(()),
+            waiting: SyncMutex::new(None),
+            open: SyncMutex::new(None),
+            seq: AtomicU64::new(0),
+            link: watch::channel(Link::Reconnecting).0,
+            queue,
+            answer,
+        }
+    }
+
+    pub fn link(&self) -> Link {
+        *self.link.borrow()
+    }
+
+    /// changes to the [`Link`]
+    pub fn watch(&self) -> watch::Receiver<Link> {
+        self.link.subscribe()
+    }
+
+    /// losing the console fails the request in flight: its answer isnt coming.
+    pub fn set(&self, link: Link) {
+        if self.link.send_replace(link) == Link::Connected && link != Link::Connected {
+            self.waiting.lock().unwrap().take();
+            self.open.lock().unwrap().take();
+        }
+    }
+
+    /// wait for the console to be connected, if we can.
+    async fn ready(&self) -> Result<()> {
+        let mut link = self.link.subscribe();
+        let now = *link.borrow();
+        if now == Link::Connected {
+            return Ok(());
+        }
+        if self.queue.is_zero() {
+            return Err(Error::Disconnected {
+                link: now,
+                why: "not taking commands",
+            });
+        }
+        let back = timeout(self.queue, link.wait_for(|&l| l == Link::Connected))
+            .await
+            .is_ok_and(|x| x.is_ok());
+        if back {
+            return Ok(());
+        }
+        Err(Error::Disconnected {
+            link: self.link(),
+            why: "it didnt come back in time",
+        })
+    }
+
+    fn closed(&self) -> Error {
+        Error::Disconnected {
+            link: self.link(),
+            why: "nothing is reading commands",
+        }
+    }
+
+    /// send a command, leaving its output to everyone else.
+    /// waits for its output (a while) before the next command goes, so it isnt mistaken for that ones.
+    /// while the console is gone, commands queue up (in order) until it comes back or they expire.
+    pub async fn send(&self, cmd: impl Into<String>) -> Result<()> {
+        match self.exchange(cmd.into(), true).await {
+            // it went, which is all thats wanted
+            Ok(_) | Err(Error::Timeout { .. }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// send a command, and wait (a while) for its output.
+    pub async fn request(&self, cmd: impl Into<String>) -> Result<String> {
+        self.exchange(cmd.into(), false).await
+    }
+
+    async fn exchange(&self, cmd: String, keep: bool) -> Result<String> {
+        define_print!("console");
+        let _turn = self.turn.lock().await;
+        self.ready().await?;
+        let id = self.seq.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        *self.waiting.lock().unwrap() = Some(Waiter { id, tx, keep });
+        let _waiting = Waiting(self, id);
+        input!("#{id} {cmd}");
+        self.stdin
+            .send(format!("js \"{START} {id}\"\n{cmd}\njs \"{END} {id}\""))
+            .map_err(|_| self.closed())?;
+        match timeout(self.answer, rx).await {
+            Ok(Ok(answer)) => Ok(answer),
+            Ok(Err(_)) => Err(Error::Disconnected {
+                link: self.link(),
+                why: "lost before it answered",
+            }),
+            Err(_) => Err(Error::Timeout {
+                cmd,
+                after: self.answer,
+            }),
+        }
+    }
+
+    /// take the output of commands out of a block, and give it to whoever sent them.
+    /// returns whats left for everyone else: output nobody asked for, or nobody waited for,
+    /// and chat, joins and leaves, even when they come in the middle of a commands output.
+    pub fn route(&self, block: String) -> Option<String> {
+        let mut rest = String::new();
+        let mut open = self.open.lock().unwrap();
+        for line in block.lines() {
+            if let Some(id) = mark(line, START) {
+                *open = Some((id, String::new()));
+            } else if let Some(id) = mark(line, END) {
+                if let Some((opened, output)) = open.take()
+                    && opened == id
+                {
+                    self.answer(id, output, &mut rest);
+                }
+            } else {
+                let to = match &mut *open {
+                    Some((_, output)) if !crate::webhook::unsolicited(line) => output,
+                    _ => &mut rest,
+                };
+                to.push_str(line);
+                to.push('\n');
+            }
+        }
+        rest.pop();
+        (!rest.is_empty()).then_some(rest)
+    }
+
+    /// `output` has a newline after each line
+    fn answer(&self, id: u64, mut output: String, rest: &mut String) {
+        define_print!("console");
+        let waiter = {
+            let mut waiting = self.waiting.lock().unwrap();
+            // a command that timed out answering late isnt the one waiting now
+            match &*waiting {
+                Some(w) if w.id == id => waiting.take(),
+                _ => None,
+            }
+        };
+        let Some(waiter) = waiter else {
+            rest.push_str(&output);
+            return;
+        };
+        if waiter.keep {
+            rest.push_str(&output);
+            let _ = waiter.tx.send(String::new());
+            return;
+        }
+        output.pop();
+        match waiter.tx.send(output) {
+            Ok(()) => output!("#{id} answered"),
+            Err(output) => {
+                rest.push_str(&output);
+                rest.push('\n');
+            }
+        }
+    }
+}
+
+/// the id in a `START` or `END` line
+fn mark(line: &str, kind: &str) -> Option<u64> {
+    line.strip_prefix(kind)?.strip_prefix(' ')?.parse().ok()
+}
+
+/// what the console gets for `cmd`
+#[cfg(test)]
+fn marked(id: u64, cmd: &str) -> String {
+    format!("js \"{START} {id}\"\n{cmd}\njs \"{END} {id}\"")
+}
+
+#[tokio::test]
+async fn routing() {
+    let (stdin, mut rx) = broadcast::channel(4);
+    let console = std::sync::Arc::new(Console::new(stdin, Duration::ZERO, Duration::from_secs(5)));
+    assert!(console.send("status").await.is_err());
+    console.set(Link::Connected);
+    assert_eq!(console.route("chatter".into()), Some("chatter".into()));
+    let c = console.clone();
+    let req = tokio::spawn(async move { c.request("status").await.unwrap() });
+    assert_eq!(rx.recv().await.unwrap(), marked(0, "status"));
+    // from before the command went
+    assert_eq!(
+        console.route("chatter\npanel:start 0\n57 TPS".into()),
+        Some("chatter".into())
+    );
+    // chat isnt anyones answer
+    assert_eq!(
+        console.route("nile: hi\npanel:end 0".into()),
+        Some("nile: hi".into())
+    );
+    assert_eq!(req.await.unwrap(), "57 TPS");
+    assert_eq!(console.route("chatter".into()), Some("chatter".into()));
+
+    // sends wait for their output, but leave it to everyone
+    let c = console.clone();
+    let send = tokio::spawn(async move { c.send("save").await });
+    assert_eq!(rx.recv().await.unwrap(), marked(1, "save"));
+    assert_eq!(
+        console.route("panel:start 1\nSaved to slot 0.\npanel:end 1".into()),
+        Some("Saved to slot 0.".into())
+    );
+    send.await.unwrap().unwrap();
+
+    let c = console.clone();
+    let req = tokio::spawn(async move { c.request("players").await });
+    assert_eq!(rx.recv().await.unwrap(), marked(2, "players"));
+    console.set(Link::Reconnecting);
+    assert!(matches!(
+        req.await.unwrap(),
+        Err(Error::Disconnected { .. })
+    ));
+}
+
+#[tokio::test]
+async fn expiry() {
+    let (stdin, _rx) = broadcast::channel(4);
+    let console = Console::new(stdin, Duration::ZERO, Duration::from_millis(50));
+    console.set(Link::Connected);
+    assert!(matches!(
+        console.request("status").await,
+        Err(Error::Timeout { .. })
+    ));
+    // the late answer isnt anyones
+    assert_eq!(
+        console.route("panel:start 0\n57 TPS\npanel:end 0".into()),
+        Some("57 TPS".into())
+    );
+}
+
+#[tokio::test]
+async fn late() {
+    let (stdin, mut rx) = broadcast::channel(4);
+    let console = std::sync::Arc::new(Console::new(
+        stdin,
+        Duration::ZERO,
+        Duration::from_millis(50),
+    ));
+    console.set(Link::Connected);
+    assert!(matches!(
+        console.request("players").await,
+        Err(Error::Timeout { .. })
+    ));
+    assert_eq!(rx.recv().await.unwrap(), marked(0, "players"));
+    let c = console.clone();
+    let req = tokio::spawn(async move { c.request("status").await });
+    assert_eq!(rx.recv().await.unwrap(), marked(1, "status"));
+    // the answer to `players` shows up while `status` waits
+    assert_eq!(
+        console.route("panel:start 0\n[] nile\npanel:end 0".into()),
+        Some("[] nile".into())
+    );
+    assert_eq!(
+        console.route("panel:start 1\n57 TPS\npanel:end 1".into()),
+        None
+    );
+    assert_eq!(req.await.unwrap().unwrap(), "57 TPS");
+}
+
+#[tokio::test]
+async fn queueing() {
+    let (stdin, mut rx) = broadcast::channel(4);
+    let console = std::sync::Arc::new(Console::new(
+        stdin,
+        Duration::from_secs(5),
+        Duration::from_secs(5),
+    ));
+    let c = console.clone();
+    let send = tokio::spawn(async move { c.send("say hi").await });
+    tokio::task::yield_now().await;
+    assert!(rx.try_recv().is_err());
+    console.set(Link::Connected);
+    assert_eq!(rx.recv().await.unwrap(), marked(0, "say hi"));
+    console.route("panel:start 0\npanel:end 0".into());
+    send.await.unwrap().unwrap();
+}
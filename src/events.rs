@@ -0,0 +1,111 @@
+//! `/events`: what happens in the game (joins, chat, map changes), as server-sent events.
+use crate::auth::{Access, Scope};
+use crate::fanout::Overflow;
+use crate::server::{pick, Servers};
+use crate::webhook::Message;
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::sse::{Event as Sse, KeepAlive},
+    response::IntoResponse,
+};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// a [`Message`], and when it happened.
+#[derive(Clone, Debug, serde_derive::Serialize)]
+pub struct Event {
+    /// unix milliseconds
+    pub at: u64,
+    #[serde(flatten)]
+    pub message: Message,
+}
+
+impl Event {
+    pub fn now(message: Message) -> Self {
+        Self {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            message,
+        }
+    }
+
+    /// admin chat, kicks, bans and crashes are for admins
+    fn visible(&self, access: &Access) -> bool {
+        !matches!(
+            self.message,
+            Message::AdminChat { .. }
+                | Message::Kick { .. }
+                | Message::Ban { .. }
+                | Message::Crash { .. }
+        ) || access.can(Scope::Moderate)
+    }
+}
+
+/// `/events/<server>`: `{"at", "type", ...}` for each message, as it happens.
+pub async fn events(
+    axum::extract::State(servers): axum::extract::State<Arc<Servers>>,
+    server: Option<Path<String>>,
+    access: Access,
+) -> Result<impl IntoResponse, StatusCode> {
+    let s = pick(&servers, server)?;
+    // a reader that cant keep up gets cut off, like `/scrollback?follow=true`
+    let rx = s.events.subscribe("events", 256, Overflow::Disconnect);
+    let stream = futures::stream::unfold((rx, access), |(mut rx, access)| async move {
+        loop {
+            let e = rx.recv().await?;
+            if e.visible(&access) {
+                let data = serde_json::to_string(&e).unwrap();
+                return Some((Ok::<_, Infallible>(Sse::default().data(data)), (rx, access)));
+            }
+        }
+    });
+    Ok(axum::response::sse::Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[test]
+fn json() {
+    let e = Event {
+        at: 1700000000000,
+        message: Message::Chat {
+            player: "nile".into(),
+            content: "hello".into(),
+        },
+    };
+    assert_eq!(
+        serde_json::to_string(&e).unwrap(),
+        r#"{"at":1700000000000,"type":"chat","player":"nile","content":"hello"}"#
+    );
+    let admin = Event::now(Message::AdminChat {
+        player: "nile".into(),
+        content: "/a hi".into(),
+    });
+    assert!(serde_json::to_string(&admin)
+        .unwrap()
+        .contains(r#""type":"admin_chat""#));
+    assert!(!admin.visible(&Access(None)));
+    assert!(!admin.visible(&Access(Some(Scope::Read))));
+    assert!(admin.visible(&Access(Some(Scope::Moderate))));
+    assert!(e.visible(&Access(None)));
+    for m in [
+        Message::Kick {
+            by: "bendn".into(),
+            player: "nile".into(),
+        },
+        Message::Ban {
+            by: "bendn".into(),
+            player: "nile".into(),
+        },
+        Message::Crash {
+            error: "java.lang.NullPointerException".into(),
+            message: Some("oh no".into()),
+        },
+    ] {
+        let e = Event::now(m);
+        assert!(!e.visible(&Access(None)));
+        assert!(!e.visible(&Access(Some(Scope::Read))));
+        assert!(e.visible(&Access(Some(Scope::Moderate))));
+    }
+}
@@ -0,0 +1,137 @@
+//! a stand-in for a mindustry server's console socket, so the console, the bot's parsers
+//! and the relay can be tested without java or discord.
+use crate::fanout::{Overflow, Subscriber};
+use crate::process::Endpoint;
+use crate::server::State;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+/// the replies of a real server, see `fixtures/console.txt`
+pub const CONSOLE: &str = include_str!("../fixtures/console.txt");
+
+/// what to answer to what.
+#[derive(Clone, Default)]
+pub struct Fake {
+    replies: Vec<(String, String)>,
+}
+
+impl Fake {
+    /// `> command` starts the reply to `command`, up to the next `>`. `#` starts a comment.
+    pub fn script(script: &str) -> Self {
+        let mut fake = Self::default();
+        for line in script.lines().filter(|l| !l.starts_with('#')) {
+            if let Some(cmd) = line.strip_prefix("> ") {
+                fake.replies.push((cmd.to_owned(), String::new()));
+            } else if let Some((_, reply)) = fake.replies.last_mut() {
+                reply.push_str(line);
+                reply.push('\n');
+            }
+        }
+        fake
+    }
+
+    pub fn on(mut self, cmd: &str, reply: &str) -> Self {
+        self.replies.retain(|(c, _)| c != cmd);
+        self.replies.push((cmd.to_owned(), format!("{reply}\n")));
+        self
+    }
+
+    fn reply(&self, cmd: &str) -> String {
+        // just enough js for [`crate::console`]s marks
+        if let Some(said) = cmd.strip_prefix("js \"").and_then(|x| x.strip_suffix('"')) {
+            return format!("{said}\n");
+        }
+        self.replies
+            .iter()
+            .find(|(c, _)| c == cmd)
+            .or_else(|| {
+                self.replies
+                    .iter()
+                    .find(|(c, _)| cmd.strip_prefix(&**c).is_some_and(|x| x.starts_with(' ')))
+            })
+            .map_or_else(
+                || "Invalid command. Type 'help' for help.\n".to_owned(),
+                |(_, r)| r.clone(),
+            )
+    }
+
+    /// listen on some local port.
+    pub async fn spawn(self) -> Running {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let at = listener.local_addr().unwrap();
+        let (say, mut said) = mpsc::unbounded_channel::<String>();
+        let heard = Arc::new(Mutex::new(vec![]));
+        let h = heard.clone();
+        tokio::spawn(async move {
+            // one connection at a time, like the real thing
+            while let Ok((stream, _)) = listener.accept().await {
+                let (read, mut write) = stream.into_split();
+                let mut lines = BufReader::new(read).lines();
+                loop {
+                    let out = tokio::select! {
+                        line = lines.next_line() => match line {
+                            Ok(Some(cmd)) => {
+                                let reply = self.reply(&cmd);
+                                h.lock().unwrap().push(cmd);
+                                reply
+                            }
+                            _ => break,
+                        },
+                        Some(line) = said.recv() => format!("{line}\n"),
+                    };
+                    if write.write_all(out.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Running {
+            at: Endpoint::Tcp(at.ip().to_string(), at.port()),
+            say,
+            heard,
+        }
+    }
+}
+
+/// a [`Fake`] thats listening.
+pub struct Running {
+    pub at: Endpoint,
+    say: mpsc::UnboundedSender<String>,
+    heard: Arc<Mutex<Vec<String>>>,
+}
+
+impl Running {
+    /// print something unprompted, like a chat message
+    pub fn say(&self, line: &str) {
+        self.say.send(line.to_owned()).unwrap();
+    }
+
+    /// the commands received so far, besides [`crate::console`]s marks
+    pub fn heard(&self) -> Vec<String> {
+        self.heard
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|c| !c.starts_with("js \"panel:"))
+            .cloned()
+            .collect()
+    }
+
+    /// a server connected to this, and the output that wasnt an answer (what the relay gets).
+    pub fn server(&self) -> (Arc<State>, Subscriber<String>) {
+        let s = State::fake(self.at.clone());
+        let stdout = s.stdout.subscribe("relay", 64, Overflow::Block);
+        (s, stdout)
+    }
+}
+
+#[test]
+fn script() {
+    let fake = Fake::script(CONSOLE).on("status", "60 TPS / 1 MB / 0 PLAYERS");
+    assert_eq!(fake.reply("status"), "60 TPS / 1 MB / 0 PLAYERS\n");
+    assert!(fake.reply("lb surv").starts_with("top survivors\n"));
+    assert!(fake.reply("trace bendn").contains("\"ln\":\"bendn\""));
+    assert!(fake.reply("lbx").starts_with("Invalid"));
+}
@@ -0,0 +1,285 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// what to do when a subscriber's queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// forget the oldest queued item
+    DropOldest,
+    /// make the sender wait (and with it, the console)
+    Block,
+    /// cut the subscriber off
+    Disconnect,
+}
+
+impl FromStr for Overflow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "drop-oldest" | "drop" => Ok(Self::DropOldest),
+            "block" => Ok(Self::Block),
+            "disconnect" => Ok(Self::Disconnect),
+            _ => anyhow::bail!("{s:?} isnt drop-oldest, block or disconnect"),
+        }
+    }
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::DropOldest => "drop-oldest",
+            Self::Block => "block",
+            Self::Disconnect => "disconnect",
+        })
+    }
+}
+
+struct Queue<T> {
+    name: String,
+    cap: usize,
+    overflow: Overflow,
+    items: Mutex<VecDeque<T>>,
+    /// theres something to receive (or the queue closed)
+    ready: Notify,
+    /// theres room to send (or the queue closed)
+    space: Notify,
+    closed: AtomicBool,
+    dropped: AtomicU64,
+    blocked: AtomicU64,
+}
+
+impl<T> Queue<T> {
+    fn is_full(&self) -> bool {
+        self.items.lock().unwrap().len() >= self.cap
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Relaxed);
+        self.ready.notify_one();
+        self.space.notify_one();
+    }
+
+    fn push(&self, item: T) {
+        let mut items = self.items.lock().unwrap();
+        if items.len() >= self.cap {
+            match self.overflow {
+                Overflow::DropOldest => {
+                    items.pop_front();
+                    self.dropped.fetch_add(1, Relaxed);
+                }
+                // the sender waits for space, so its only full if the subscriber left
+                Overflow::Block => return,
+                Overflow::Disconnect => {
+                    self.dropped.fetch_add(items.len() as u64 + 1, Relaxed);
+                    items.clear();
+                    drop(items);
+                    self.close();
+                    return;
+                }
+            }
+        }
+        items.push_back(item);
+        drop(items);
+        self.ready.notify_one();
+    }
+}
+
+/// how a subscriber is keeping up.
+#[derive(Clone, Debug)]
+pub struct Stats {
+    pub name: String,
+    pub queued: usize,
+    pub cap: usize,
+    pub overflow: Overflow,
+    /// items this subscriber never got
+    pub dropped: u64,
+    /// sends that had to wait for this subscriber
+    pub blocked: u64,
+}
+
+/// sends every item to every subscriber, each through its own bounded queue.
+///
+/// unlike a broadcast channel, a slow subscriber only affects itself
+/// (unless it asked for [`Overflow::Block`]), and sending with nobody listening is fine.
+pub struct Fanout<T> {
+    subs: Mutex<Vec<Arc<Queue<T>>>>,
+}
+
+impl<T: Clone> Fanout<T> {
+    pub const fn new() -> Self {
+        Self {
+            subs: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn receivers(&self) -> usize {
+        let mut subs = self.subs.lock().unwrap();
+        subs.retain(|q| !q.closed.load(Relaxed));
+        subs.len()
+    }
+
+    pub fn subscribe(
+        &self,
+        name: impl Into<String>,
+        cap: usize,
+        overflow: Overflow,
+    ) -> Subscriber<T> {
+        self.subscribe_with(name, cap, overflow, || ()).1
+    }
+
+    /// subscribe, running `f` where nothing can be sent, so whatever it
+    /// looks at (like [`Scrollback::tail`](crate::scrollback::Scrollback::tail)) is exactly
+    /// everything before the first item received.
+    pub fn subscribe_with<R>(
+        &self,
+        name: impl Into<String>,
+        cap: usize,
+        overflow: Overflow,
+        f: impl FnOnce() -> R,
+    ) -> (R, Subscriber<T>) {
+        let queue = Arc::new(Queue {
+            name: name.into(),
+            cap: cap.max(1),
+            overflow,
+            items: Mutex::new(VecDeque::new()),
+            ready: Notify::new(),
+            space: Notify::new(),
+            closed: AtomicBool::new(false),
+            dropped: AtomicU64::new(0),
+            blocked: AtomicU64::new(0),
+        });
+        let mut subs = self.subs.lock().unwrap();
+        subs.push(queue.clone());
+        (f(), Subscriber { queue })
+    }
+
+    pub async fn send(&self, item: T) {
+        self.send_with(item, |_| {}).await;
+    }
+
+    /// send to everyone, running `f` at the moment its sent (see [`subscribe_with`](Self::subscribe_with)).
+    pub async fn send_with(&self, item: T, f: impl FnOnce(&T)) {
+        let mut waited = false;
+        loop {
+            let full = {
+                let mut subs = self.subs.lock().unwrap();
+                subs.retain(|q| !q.closed.load(Relaxed));
+                match subs
+                    .iter()
+                    .find(|q| q.overflow == Overflow::Block && q.is_full())
+                {
+                    Some(q) => q.clone(),
+                    None => {
+                        // before anyone can have it, so whatever `f` records is there when they look
+                        f(&item);
+                        for q in &*subs {
+                            q.push(item.clone());
+                        }
+                        return;
+                    }
+                }
+            };
+            if !waited {
+                full.blocked.fetch_add(1, Relaxed);
+                waited = true;
+            }
+            full.space.notified().await;
+        }
+    }
+
+    pub fn stats(&self) -> Vec<Stats> {
+        self.subs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|q| !q.closed.load(Relaxed))
+            .map(|q| Stats {
+                name: q.name.clone(),
+                queued: q.items.lock().unwrap().len(),
+                cap: q.cap,
+                overflow: q.overflow,
+                dropped: q.dropped.load(Relaxed),
+                blocked: q.blocked.load(Relaxed),
+            })
+            .collect()
+    }
+}
+
+impl<T> Drop for Fanout<T> {
+    fn drop(&mut self) {
+        for q in &*self.subs.lock().unwrap() {
+            q.close();
+        }
+    }
+}
+
+/// one end of a [`Fanout`]. dropping it unsubscribes.
+pub struct Subscriber<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Subscriber<T> {
+    /// the next item, or `None` once disconnected.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            if let Some(item) = self.queue.items.lock().unwrap().pop_front() {
+                self.queue.space.notify_one();
+                return Some(item);
+            }
+            if self.queue.closed.load(Relaxed) {
+                return None;
+            }
+            self.queue.ready.notified().await;
+        }
+    }
+
+    /// items lost to [`Overflow::DropOldest`] so far
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Relaxed)
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+#[tokio::test]
+async fn overflow() {
+    let out = Fanout::new();
+    let mut old = out.subscribe("old", 2, Overflow::DropOldest);
+    let mut cut = out.subscribe("cut", 2, Overflow::Disconnect);
+    for i in 0..3 {
+        out.send(i).await;
+    }
+    assert_eq!(old.dropped(), 1);
+    assert_eq!(old.recv().await, Some(1));
+    assert_eq!(old.recv().await, Some(2));
+    assert_eq!(cut.recv().await, None);
+    assert_eq!(out.receivers(), 1);
+
+    let out = Arc::new(Fanout::new());
+    let mut slow = out.subscribe("slow", 1, Overflow::Block);
+    out.send(0).await;
+    let o = out.clone();
+    let blocked = tokio::spawn(async move { o.send(1).await });
+    tokio::task::yield_now().await;
+    assert_eq!(out.stats()[0].blocked, 1);
+    assert_eq!(slow.recv().await, Some(0));
+    blocked.await.unwrap();
+    assert_eq!(slow.recv().await, Some(1));
+    drop(slow);
+    out.send(2).await;
+    assert_eq!(out.receivers(), 0);
+
+    let sub = out.subscribe("first", 1, Overflow::Block);
+    out.send_with(3, |_| assert!(sub.queue.items.lock().unwrap().is_empty()))
+        .await;
+    assert_eq!(sub.queue.items.lock().unwrap().len(), 1);
+}
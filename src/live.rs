@@ -0,0 +1,124 @@
+use crate::auth::{given, Access, Scope};
+use crate::fanout::Overflow;
+use crate::scrollback::Line;
+use crate::server::{pick, Servers, State};
+use axum::{
+    extract::{
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Path,
+    },
+    http::StatusCode,
+    response::Response,
+};
+use serde_json::json;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// commands waiting for the console, per socket
+const WAITING: usize = 16;
+
+/// `/console/ws/:server` (admins only): the console, live. the first message sent to it is an
+/// admin token (anything, if logged in), so it isnt in the url. then it sends the recent
+/// scrollback, and output as it comes, one `{"at", "text"}` per line. text sent to it after
+/// is run as a command.
+pub async fn console(
+    axum::extract::State(servers): axum::extract::State<Arc<Servers>>,
+    server: Option<Path<String>>,
+    access: Access,
+    at: Option<ConnectInfo<SocketAddr>>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, StatusCode> {
+    let state = pick(&servers, server)?;
+    let admin = access.can(Scope::Admin);
+    let ip = at.map(|ConnectInfo(at)| at.ip());
+    Ok(ws.on_upgrade(move |socket| live(socket, state, admin, ip)))
+}
+
+/// whether the first message lets them in
+async fn allowed(socket: &mut WebSocket, admin: bool, ip: Option<IpAddr>) -> bool {
+    let first = tokio::time::timeout(Duration::from_secs(10), socket.recv()).await;
+    let Ok(Some(Ok(Message::Text(token)))) = first else {
+        return false;
+    };
+    if admin || given(&token, ip) >= Some(Scope::Admin) {
+        return true;
+    }
+    let _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code: 4001,
+            reason: "log in, or give an admin token".into(),
+        })))
+        .await;
+    false
+}
+
+async fn live(mut socket: WebSocket, s: Arc<State>, admin: bool, ip: Option<IpAddr>) {
+    if !allowed(&mut socket, admin, ip).await {
+        return;
+    }
+    let text = |l: &Line| Message::Text(serde_json::to_string(l).unwrap());
+    let (lines, mut out) = s
+        .stdout
+        .subscribe_with("websocket", 256, Overflow::Disconnect, || {
+            s.scrollback.tail(100)
+        });
+    for line in &lines {
+        if socket.send(text(line)).await.is_err() {
+            return;
+        }
+    }
+    // sent from elsewhere, so waiting on the console doesnt hold up output (and get it cut off)
+    let (cmds, mut todo) = mpsc::channel::<String>(WAITING);
+    let (failed, mut errors) = mpsc::unbounded_channel();
+    let console = s.clone();
+    tokio::spawn(async move {
+        while let Some(cmd) = todo.recv().await {
+            if let Err(e) = console.console.send(cmd).await
+                && failed.send(e.to_string()).is_err()
+            {
+                return;
+            }
+        }
+    });
+    loop {
+        tokio::select! {
+            Some(e) = errors.recv() => {
+                let e = json!({ "error": e }).to_string();
+                if socket.send(Message::Text(e)).await.is_err() {
+                    return;
+                }
+            }
+            block = out.recv() => {
+                let Some(block) = block else {
+                    let _ = socket
+                        .send(Message::Close(Some(CloseFrame {
+                            code: 1008,
+                            reason: "fell behind".into(),
+                        })))
+                        .await;
+                    return;
+                };
+                for line in block.lines() {
+                    if socket.send(text(&Line::now(line))).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Text(cmd))) => {
+                    println!("{}: web console: {cmd}", s.name);
+                    if cmds.try_send(cmd).is_err() {
+                        let e = json!({ "error": "too many commands waiting" }).to_string();
+                        if socket.send(Message::Text(e)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
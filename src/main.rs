@@ -4,9 +4,27 @@ use std::str::FromStr;
 #[macro_use]
 mod logging;
 mod alerts;
+mod api;
+mod archive;
+mod auth;
+mod avatars;
 mod bot;
+mod cache;
+mod chat;
+mod console;
+mod events;
+#[cfg(test)]
+mod fake;
+mod fanout;
+mod live;
+mod mentions;
+mod metrics;
 mod process;
+mod scrollback;
 mod server;
+mod supervisor;
+mod timelapse;
+mod view;
 mod webhook;
 
 use server::*;
@@ -14,6 +32,13 @@ use std::net::SocketAddr;
 emojib::the_crate! {}
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
+    if std::env::args().nth(1).as_deref() == Some("hash") {
+        // `echo hunter2 | panel hash`, for auth.json
+        let mut password = String::new();
+        std::io::stdin().read_line(&mut password).unwrap();
+        println!("{}", auth::hash(password.trim_end_matches(['\r', '\n'])));
+        return;
+    }
     tokio::spawn(alerts::run());
     Server::spawn(SocketAddr::from((
         [0, 0, 0, 0],
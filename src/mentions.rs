@@ -0,0 +1,278 @@
+//! who gets pinged when a player types something, from `mentions.json` (or `$MENTIONS`).
+//! edited with `/relay mentions`.
+use anyhow::Result;
+use poise::serenity_prelude::{CreateAllowedMentions, RoleId, UserId};
+use regex::{NoExpand, Regex};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    Role(u64),
+    User(u64),
+}
+
+impl Target {
+    pub fn mention(self) -> String {
+        match self {
+            Self::Role(id) => format!("<@&{id}>"),
+            Self::User(id) => format!("<@{id}>"),
+        }
+    }
+}
+
+/// break mentions written out by hand (`<@&id>`, `<@id>`), as everyone in the table may be pinged:
+/// only triggers should ping.
+pub fn escape(text: &str) -> String {
+    text.replace("<@", "<\u{200b}@")
+}
+
+/// `{"trigger": "@mods", "role": 1133416252791074877}`
+#[derive(Clone, Debug, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct Rule {
+    pub trigger: String,
+    /// whether the trigger is a regex
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(flatten)]
+    pub target: Target,
+    /// seconds before the same player can ping it again
+    #[serde(default = "cooldown")]
+    pub cooldown: u64,
+}
+
+fn cooldown() -> u64 {
+    60
+}
+
+/// how a rule finds its trigger
+enum Matcher {
+    Literal,
+    Regex(Regex),
+}
+
+impl Rule {
+    fn matcher(&self) -> Result<Matcher> {
+        Ok(if self.regex {
+            Matcher::Regex(Regex::new(&self.trigger)?)
+        } else {
+            Matcher::Literal
+        })
+    }
+}
+
+/// what there was before there was a table
+fn defaults() -> Vec<Rule> {
+    const MODS: Target = Target::Role(1133416252791074877);
+    const ADMINS: Target = Target::Role(1110088946374938715);
+    [
+        ("@Moderator", MODS),
+        ("@mods", MODS),
+        ("@Administrator", ADMINS),
+        ("@admin", ADMINS),
+        ("@bendn", Target::User(696196765564534825)),
+        ("@bende", Target::User(696196765564534825)),
+        ("@nile", Target::User(600014432298598400)),
+        ("@proto", Target::User(1173213085553660034)),
+        ("grief", Target::Role(1206743548838416455)),
+        ("/votekick", Target::Role(1206743639397630003)),
+    ]
+    .into_iter()
+    .map(|(trigger, target)| Rule {
+        trigger: trigger.into(),
+        regex: false,
+        target,
+        cooldown: cooldown(),
+    })
+    .collect()
+}
+
+pub struct Mentions {
+    /// where changes are saved. none in tests
+    path: Option<PathBuf>,
+    rules: Mutex<Vec<(Rule, Matcher)>>,
+    /// (player, trigger) -> when they last pinged it
+    pinged: Mutex<HashMap<(String, String), Instant>>,
+}
+
+pub static MENTIONS: LazyLock<Mentions> = LazyLock::new(Mentions::load);
+
+impl Mentions {
+    fn load() -> Self {
+        let path =
+            PathBuf::from(std::env::var("MENTIONS").unwrap_or_else(|_| "mentions.json".into()));
+        let rules = std::fs::read_to_string(&path).map_or_else(
+            |_| defaults(),
+            |x| {
+                serde_json::from_str(&x)
+                    .unwrap_or_else(|e| panic!("{} isnt valid: {e}", path.display()))
+            },
+        );
+        let mut m = Self::new(rules).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+        m.path = Some(path);
+        m
+    }
+
+    fn new(rules: Vec<Rule>) -> Result<Self> {
+        Ok(Self {
+            path: None,
+            rules: Mutex::new(
+                rules
+                    .into_iter()
+                    .map(|r| -> Result<_> {
+                        let m = r.matcher()?;
+                        Ok((r, m))
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            pinged: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn save(&self, rules: &[(Rule, Matcher)]) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let rules = rules.iter().map(|(r, _)| r).collect::<Vec<_>>();
+        std::fs::write(path, serde_json::to_string_pretty(&rules)?)?;
+        Ok(())
+    }
+
+    /// turn `player`s triggers into pings, unless theyve pinged it too recently.
+    pub fn apply(&self, player: &str, line: &str, now: Instant) -> String {
+        let rules = self.rules.lock().unwrap();
+        let mut pinged = self.pinged.lock().unwrap();
+        // forget pings that cant hold anything back anymore
+        pinged.retain(|(_, trigger), then| {
+            rules.iter().any(|(rule, _)| {
+                rule.trigger == *trigger
+                    && now.duration_since(*then) < Duration::from_secs(rule.cooldown)
+            })
+        });
+        let mut line = escape(line);
+        for (rule, matcher) in &*rules {
+            let found = match matcher {
+                Matcher::Literal => line.contains(&rule.trigger),
+                Matcher::Regex(r) => r.is_match(&line),
+            };
+            if !found {
+                continue;
+            }
+            let key = (player.to_owned(), rule.trigger.clone());
+            if let Some(&then) = pinged.get(&key)
+                && now.duration_since(then) < Duration::from_secs(rule.cooldown)
+            {
+                continue;
+            }
+            pinged.insert(key, now);
+            let mention = rule.target.mention();
+            line = match matcher {
+                Matcher::Literal => line.replace(&rule.trigger, &mention),
+                Matcher::Regex(r) => r.replace_all(&line, NoExpand(&mention)).into_owned(),
+            };
+        }
+        line
+    }
+
+    /// only the table can ping
+    pub fn allowed(&self) -> CreateAllowedMentions {
+        let rules = self.rules.lock().unwrap();
+        let (mut roles, mut users) = (vec![], vec![]);
+        for (rule, _) in &*rules {
+            match rule.target {
+                Target::Role(id) => roles.push(RoleId::new(id)),
+                Target::User(id) => users.push(UserId::new(id)),
+            }
+        }
+        roles.sort();
+        roles.dedup();
+        users.sort();
+        users.dedup();
+        CreateAllowedMentions::default().roles(roles).users(users)
+    }
+
+    /// add a rule, replacing any with the same trigger
+    pub fn add(&self, rule: Rule) -> Result<()> {
+        let matcher = rule.matcher()?;
+        let mut rules = self.rules.lock().unwrap();
+        rules.retain(|(r, _)| r.trigger != rule.trigger);
+        rules.push((rule, matcher));
+        self.save(&rules)
+    }
+
+    /// whether there was a rule to remove
+    pub fn remove(&self, trigger: &str) -> Result<bool> {
+        let mut rules = self.rules.lock().unwrap();
+        let before = rules.len();
+        rules.retain(|(r, _)| r.trigger != trigger);
+        if rules.len() == before {
+            return Ok(false);
+        }
+        self.save(&rules)?;
+        Ok(true)
+    }
+
+    pub fn list(&self) -> Vec<Rule> {
+        self.rules
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(r, _)| r.clone())
+            .collect()
+    }
+}
+
+#[test]
+fn mentions() {
+    let m = Mentions::new(defaults()).unwrap();
+    let now = Instant::now();
+    assert_eq!(
+        m.apply("nile", "@mods grief", now),
+        "<@&1133416252791074877> <@&1206743548838416455>"
+    );
+    // too soon
+    assert_eq!(m.apply("nile", "@mods", now), "@mods");
+    assert_eq!(m.apply("proto", "@mods", now), "<@&1133416252791074877>");
+    // only triggers ping
+    assert_eq!(
+        m.apply("eris", "<@&1133416252791074877> <@600014432298598400>", now),
+        "<\u{200b}@&1133416252791074877> <\u{200b}@600014432298598400>"
+    );
+    assert_eq!(
+        m.apply("nile", "@mods", now + Duration::from_secs(60)),
+        "<@&1133416252791074877>"
+    );
+
+    m.add(Rule {
+        trigger: r"(?i)\bhelp+\b".into(),
+        regex: true,
+        target: Target::User(1),
+        cooldown: 0,
+    })
+    .unwrap();
+    assert_eq!(m.apply("nile", "HELPPP me", now), "<@1> me");
+    // cooled down pings are forgotten
+    assert!(!m.pinged.lock().unwrap().is_empty());
+    m.apply("nile", "", now + Duration::from_secs(60 * 60));
+    assert!(m.pinged.lock().unwrap().is_empty());
+    assert!(m
+        .add(Rule {
+            trigger: "(".into(),
+            regex: true,
+            target: Target::User(1),
+            cooldown: 0,
+        })
+        .is_err());
+    assert!(m.remove("@mods").unwrap());
+    assert!(!m.remove("@mods").unwrap());
+    assert_eq!(m.apply("bendn", "@mods", now), "@mods");
+
+    let rule: Rule =
+        serde_json::from_str(r#"{"trigger": "@nile", "user": 600014432298598400}"#).unwrap();
+    assert_eq!(rule.target, Target::User(600014432298598400));
+    assert_eq!(rule.cooldown, 60);
+    assert!(!rule.regex);
+}
@@ -0,0 +1,213 @@
+//! `/metrics`, for prometheus.
+use crate::console::Link;
+use crate::server::{Servers, State};
+use axum::{
+    http::header::CONTENT_TYPE,
+    response::{AppendHeaders, IntoResponse},
+};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// webhook messages discord refused
+pub static WEBHOOK_FAILURES: AtomicU64 = AtomicU64::new(0);
+/// syn packets seen by [`crate::alerts::run`]
+pub static SYN: AtomicU64 = AtomicU64::new(0);
+/// (server, stage) -> (renders, seconds)
+type Renders = BTreeMap<(String, &'static str), (u64, f64)>;
+static RENDERS: Mutex<Renders> = Mutex::new(BTreeMap::new());
+/// command -> (invocations, errors)
+static COMMANDS: Mutex<BTreeMap<String, (u64, u64)>> = Mutex::new(BTreeMap::new());
+
+/// a map image was made, taking this long for each stage
+pub fn rendered(server: &str, stages: [(&'static str, Duration); 3]) {
+    let mut renders = RENDERS.lock().unwrap();
+    for (stage, took) in stages {
+        let (n, secs) = renders.entry((server.to_owned(), stage)).or_default();
+        *n += 1;
+        *secs += took.as_secs_f64();
+    }
+}
+
+pub fn invoked(command: &str) {
+    COMMANDS
+        .lock()
+        .unwrap()
+        .entry(command.to_owned())
+        .or_default()
+        .0 += 1;
+}
+
+pub fn failed(command: &str) {
+    COMMANDS
+        .lock()
+        .unwrap()
+        .entry(command.to_owned())
+        .or_default()
+        .1 += 1;
+}
+
+/// for label values
+fn escape(s: &str) -> String {
+    s.replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+/// `# HELP` and `# TYPE`
+fn head(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}").unwrap();
+}
+
+/// a metric, with a line per set of labels
+fn metric(
+    out: &mut String,
+    [name, kind, help]: [&str; 3],
+    rows: impl IntoIterator<Item = (String, f64)>,
+) {
+    head(out, name, kind, help);
+    for (labels, v) in rows {
+        writeln!(out, "{name}{{{labels}}} {v}").unwrap();
+    }
+}
+
+fn server(s: &State) -> String {
+    format!("server=\"{}\"", escape(&s.name))
+}
+
+async fn gather(servers: impl Iterator<Item = &Arc<State>>) -> String {
+    let servers = servers.collect::<Vec<_>>();
+    // ask everyone at once, so one slow server doesnt add up. shared with `/api`, so scrapes
+    // dont cost the console anything extra
+    let statuses = futures::future::join_all(
+        servers
+            .iter()
+            .map(|s| async move { s.recent.status(s).await.ok() }),
+    )
+    .await;
+    let all = || servers.iter().zip(&statuses);
+    let bool = |x: bool| f64::from(u8::from(x));
+    let mut out = String::new();
+    let o = &mut out;
+    metric(
+        o,
+        ["panel_up", "gauge", "whether the server answered `status`"],
+        all().map(|(s, st)| (server(s), bool(st.is_some()))),
+    );
+    metric(
+        o,
+        ["panel_tps", "gauge", "server ticks per second"],
+        all().filter_map(|(s, st)| Some((server(s), f64::from(st.as_ref()?.0)))),
+    );
+    metric(
+        o,
+        ["panel_memory_bytes", "gauge", "server memory use"],
+        all().filter_map(|(s, st)| Some((server(s), f64::from(st.as_ref()?.1) * 1024.0 * 1024.0))),
+    );
+    metric(
+        o,
+        ["panel_players", "gauge", "players online"],
+        all().filter_map(|(s, st)| Some((server(s), f64::from(st.as_ref()?.2)))),
+    );
+    metric(
+        o,
+        [
+            "panel_console_connected",
+            "gauge",
+            "whether the console is connected",
+        ],
+        all().map(|(s, _)| (server(s), bool(s.console.link() == Link::Connected))),
+    );
+    metric(
+        o,
+        [
+            "panel_console_reconnects_total",
+            "counter",
+            "times the console connection was lost",
+        ],
+        all().map(|(s, _)| (server(s), s.reconnects.load(Relaxed) as f64)),
+    );
+
+    head(
+        o,
+        "panel_webhook_failures_total",
+        "counter",
+        "webhook messages discord refused",
+    );
+    writeln!(
+        o,
+        "panel_webhook_failures_total {}",
+        WEBHOOK_FAILURES.load(Relaxed)
+    )
+    .unwrap();
+    head(
+        o,
+        "panel_syn_packets_total",
+        "counter",
+        "syn packets logged by the kernel",
+    );
+    writeln!(o, "panel_syn_packets_total {}", SYN.load(Relaxed)).unwrap();
+
+    head(
+        o,
+        "panel_render_seconds",
+        "summary",
+        "time spent making map images, by stage",
+    );
+    for ((server, stage), (n, secs)) in &*RENDERS.lock().unwrap() {
+        let labels = format!("server=\"{}\",stage=\"{stage}\"", escape(server));
+        writeln!(o, "panel_render_seconds_sum{{{labels}}} {secs}").unwrap();
+        writeln!(o, "panel_render_seconds_count{{{labels}}} {n}").unwrap();
+    }
+
+    let commands = COMMANDS.lock().unwrap();
+    let command = |c: &str| format!("command=\"{}\"", escape(c));
+    metric(
+        o,
+        ["panel_commands_total", "counter", "discord commands run"],
+        commands.iter().map(|(c, &(n, _))| (command(c), n as f64)),
+    );
+    metric(
+        o,
+        [
+            "panel_command_errors_total",
+            "counter",
+            "discord commands that failed",
+        ],
+        commands.iter().map(|(c, &(_, n))| (command(c), n as f64)),
+    );
+    drop(commands);
+    out
+}
+
+/// `/metrics`
+pub async fn metrics(
+    axum::extract::State(servers): axum::extract::State<Arc<Servers>>,
+) -> impl IntoResponse {
+    (
+        AppendHeaders([(CONTENT_TYPE, "text/plain; version=0.0.4")]),
+        gather(servers.iter()).await,
+    )
+}
+
+#[tokio::test]
+async fn exposition() {
+    let fake = crate::fake::Fake::script(crate::fake::CONSOLE)
+        .spawn()
+        .await;
+    let (s, _stdout) = fake.server();
+    // the counters are global, so these names are only this tests
+    invoked("exposition");
+    failed("exposition");
+    rendered("exposition", [("render", Duration::from_millis(500)); 3]);
+    let out = gather([&s].into_iter()).await;
+    assert!(out.contains("panel_up{server=\"fake\"} 1\n"));
+    assert!(out.contains("panel_tps{server=\"fake\"} 57\n"));
+    assert!(out.contains("panel_players{server=\"fake\"} 2\n"));
+    assert!(out.contains("panel_commands_total{command=\"exposition\"} 1\n"));
+    assert!(out.contains("panel_command_errors_total{command=\"exposition\"} 1\n"));
+    assert!(out.contains("panel_render_seconds_count{server=\"exposition\",stage=\"render\"} 3\n"));
+    assert!(out.contains("# TYPE panel_console_reconnects_total counter\n"));
+}
@@ -1,24 +1,151 @@
+use crate::fanout::Fanout;
+use crate::scrollback::Scrollback;
+use anyhow::{bail, Context};
+use std::fmt;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::sync::broadcast::{self, error::TryRecvError};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::sync::broadcast::{self, error::RecvError};
 use tokio::task::JoinHandle;
-use tokio::time::sleep;
+use tokio::time::{sleep, timeout, Instant};
+
+/// blocks bigger than this are sent early, so a chatty server cant starve subscribers.
+const MAX_BLOCK: usize = 1 << 20;
+/// mindustrys default `socketInputPort`
+const DEFAULT_PORT: u16 = 6859;
+
+/// where the server console listens.
+#[derive(Clone, Debug, PartialEq, Eq, serde_derive::Deserialize)]
+#[serde(try_from = "String")]
+pub enum Endpoint {
+    /// `host:port`, `[v6]:port`, or just a host (on the default port)
+    Tcp(String, u16),
+    /// `unix:/path`, or any absolute/relative path
+    Unix(PathBuf),
+}
+
+impl FromStr for Endpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                bail!("no socket path in {s:?}");
+            }
+            return Ok(Self::Unix(path.into()));
+        }
+        if s.starts_with(['/', '.']) {
+            return Ok(Self::Unix(s.into()));
+        }
+        if s.is_empty() {
+            bail!("empty console address");
+        }
+        if let Ok(addr) = SocketAddr::from_str(s) {
+            return Ok(Self::Tcp(addr.ip().to_string(), addr.port()));
+        }
+        if let Ok(ip) = Ipv6Addr::from_str(s.trim_start_matches('[').trim_end_matches(']')) {
+            return Ok(Self::Tcp(ip.to_string(), DEFAULT_PORT));
+        }
+        match s.rsplit_once(':') {
+            Some((host, _)) if host.contains(':') => {
+                bail!("{s:?}: ipv6 addresses need brackets, like [::1]:{DEFAULT_PORT}")
+            }
+            Some((host, port)) => Ok(Self::Tcp(
+                host.to_owned(),
+                port.parse()
+                    .with_context(|| format!("{s:?}: bad port {port:?}"))?,
+            )),
+            None => Ok(Self::Tcp(s.to_owned(), DEFAULT_PORT)),
+        }
+    }
+}
+
+impl TryFrom<String> for Endpoint {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(host, port) if host.contains(':') => write!(f, "[{host}]:{port}"),
+            Self::Tcp(host, port) => write!(f, "{host}:{port}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// doubling waits, from a second up to about a minute.
+pub struct Backoff(u64);
+
+impl Backoff {
+    const MAX: u64 = 64;
+
+    pub const fn new() -> Self {
+        Self(1)
+    }
+
+    /// seconds the next [`wait`](Self::wait) takes
+    pub const fn secs(&self) -> u64 {
+        self.0
+    }
+
+    pub async fn wait(&mut self) {
+        sleep(Duration::from_secs(self.0)).await;
+        self.0 = (self.0 << 1).min(Self::MAX);
+    }
+
+    pub fn reset(&mut self) {
+        self.0 = 1;
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
 
 pub struct Process {
-    inner: TcpStream,
+    inner: Box<dyn Stream>,
     input: Option<broadcast::Receiver<String>>,
-    output: Option<broadcast::Sender<String>>,
+    output: Option<Arc<Fanout<String>>>,
+    scrollback: Option<Arc<Scrollback>>,
+    route: Option<Route>,
+    idle: Duration,
 }
 
+/// takes what it wants out of a block, leaving the rest
+type Route = Box<dyn Fn(String) -> Option<String> + Send>;
+
 impl Process {
-    /// spawns the server
-    pub async fn spawn() -> anyhow::Result<Self> {
-        let stream = TcpStream::connect("localhost:6859").await?;
+    /// connects to the server console
+    pub async fn spawn(at: &Endpoint, wait: Duration) -> anyhow::Result<Self> {
+        let stream: Box<dyn Stream> = match timeout(wait, async {
+            anyhow::Ok(match at {
+                Endpoint::Tcp(host, port) => {
+                    Box::new(TcpStream::connect((&**host, *port)).await?) as Box<dyn Stream>
+                }
+                Endpoint::Unix(path) => Box::new(UnixStream::connect(path).await?),
+            })
+        })
+        .await
+        {
+            Ok(stream) => stream.with_context(|| format!("connecting to {at}"))?,
+            Err(_) => bail!("connecting to {at}: timed out after {wait:?}"),
+        };
         Ok(Self {
             inner: stream,
             input: None,
             output: None,
+            scrollback: None,
+            route: None,
+            idle: Duration::from_millis(100),
         })
     }
 
@@ -27,48 +154,342 @@ impl Process {
         self
     }
 
-    pub fn output(mut self, output: broadcast::Sender<String>) -> Self {
+    pub fn output(mut self, output: Arc<Fanout<String>>) -> Self {
         self.output = Some(output);
         self
     }
 
+    /// remember the output here too
+    pub fn scrollback(mut self, scrollback: Arc<Scrollback>) -> Self {
+        self.scrollback = Some(scrollback);
+        self
+    }
+
+    /// answers to commands, before anyone else sees the output
+    pub fn route(mut self, route: impl Fn(String) -> Option<String> + Send + 'static) -> Self {
+        self.route = Some(Box::new(route));
+        self
+    }
+
+    /// how long the console has to be quiet before the output so far is sent as one block.
+    pub fn idle(mut self, idle: Duration) -> Self {
+        self.idle = idle;
+        self
+    }
+
     pub fn link(mut self) -> JoinHandle<()> {
         define_print!("process");
         let mut input = self.input.unwrap();
         let output = self.output.unwrap();
         tokio::spawn(async move {
             let mut stdout = [0; 4096];
+            let mut framer = Framer::default();
+            // consecutive quiet periods
+            let mut quiet = 0u8;
+            // from the oldest unsent output, so a server that never goes quiet is still sent
+            let idle = sleep(self.idle);
+            tokio::pin!(idle);
             loop {
-                if output.receiver_count() == 0 {
+                if output.receivers() == 0 {
                     sleep(Duration::from_millis(500)).await;
                     continue;
                 }
-                match input.try_recv() {
-                    Err(e) => match e {
-                        TryRecvError::Closed => fail!("closed"),
-                        _ => sleep(Duration::from_millis(100)).await,
+                let block = tokio::select! {
+                    s = input.recv() => match s {
+                        Err(RecvError::Closed) => fail!("closed"),
+                        Err(RecvError::Lagged(n)) => {
+                            input!("lost {n} commands");
+                            continue;
+                        }
+                        Ok(mut s) => {
+                            input!("{s}");
+                            s.push('\n');
+                            // a broken connection ends the link, like eof, so its made again
+                            if let Err(e) = self.inner.write_all(s.as_bytes()).await {
+                                fail!("writing: {e}");
+                            }
+                            if let Err(e) = self.inner.flush().await {
+                                fail!("writing: {e}");
+                            }
+                            continue;
+                        }
                     },
-                    Ok(mut s) => {
-                        input!("{s}");
-                        s.push('\n');
-                        self.inner.write_all(s.as_bytes()).await.unwrap();
-                        self.inner.flush().await.unwrap();
-                    }
-                }
-
-                let string = {
-                    let n = tokio::select! {
-                        n = self.inner.read(&mut stdout) => n.unwrap(),
-                        () = sleep(Duration::from_millis(100)) => continue,
-                    };
-                    String::from_utf8_lossy(&strip_ansi_escapes::strip(&stdout[..n])).into_owned()
+                    n = self.inner.read(&mut stdout) => {
+                        let n = match n {
+                            Ok(n) => n,
+                            Err(e) => fail!("reading: {e}"),
+                        };
+                        if n == 0 {
+                            fail!("eof");
+                        }
+                        quiet = 0;
+                        if framer.is_empty() {
+                            idle.as_mut().reset(Instant::now() + self.idle);
+                        }
+                        framer.push(&stdout[..n]);
+                        if framer.len() <= MAX_BLOCK {
+                            continue;
+                        }
+                        framer.flush(false)
+                    },
+                    () = &mut idle, if !framer.is_empty() => {
+                        quiet = quiet.saturating_add(1);
+                        // an unterminated line gets one extra period to finish
+                        let block = framer.flush(quiet > 1);
+                        idle.as_mut().reset(Instant::now() + self.idle);
+                        block
+                    },
+                };
+                let Some(block) = block else {
+                    continue;
                 };
-                for line in string.lines() {
+                for line in block.lines() {
                     output!("{line}");
                 }
-                output.send(string).unwrap();
-                sleep(Duration::from_millis(100)).await;
+                // done here, in order with the commands written, so nothing from before
+                // a command is mistaken for its output
+                let block = match &self.route {
+                    Some(route) => route(block),
+                    None => Some(block),
+                };
+                let Some(block) = block else {
+                    continue;
+                };
+                output
+                    .send_with(block, |block| {
+                        if let Some(back) = &self.scrollback {
+                            back.record(block);
+                        }
+                    })
+                    .await;
             }
         })
     }
 }
+
+/// splits the console stream into lines, and collects lines into blocks.
+///
+/// bytes are only decoded once a line is complete, so nothing is lost to a read boundary
+/// landing inside a line (or a character).
+#[derive(Default)]
+struct Framer {
+    /// the line being received
+    partial: Vec<u8>,
+    /// complete lines not yet sent
+    block: String,
+}
+
+impl Framer {
+    fn push(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if b == b'\n' {
+                self.end_line();
+            } else {
+                self.partial.push(b);
+                // a line that never ends is cut, rather than kept forever
+                if self.partial.len() >= MAX_BLOCK {
+                    self.end_line();
+                }
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        let line = strip_ansi_escapes::strip(&self.partial);
+        self.partial.clear();
+        let line = String::from_utf8_lossy(&line);
+        if !self.block.is_empty() {
+            self.block.push('\n');
+        }
+        self.block.push_str(line.trim_end_matches('\r'));
+    }
+
+    fn len(&self) -> usize {
+        self.block.len() + self.partial.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// takes the complete lines received so far, and the unfinished line too if `partial`.
+    fn flush(&mut self, partial: bool) -> Option<String> {
+        if partial && !self.partial.is_empty() {
+            self.end_line();
+        }
+        (!self.block.is_empty()).then(|| std::mem::take(&mut self.block))
+    }
+}
+
+#[test]
+fn endpoints() {
+    macro_rules! ok {
+        ($s:literal, $e:expr) => {
+            assert_eq!(Endpoint::from_str($s).unwrap(), $e);
+        };
+    }
+    let tcp = |h: &str, p| Endpoint::Tcp(h.into(), p);
+    ok!("localhost:6859", tcp("localhost", 6859));
+    ok!("mindustry", tcp("mindustry", 6859));
+    ok!("10.0.0.2:7000", tcp("10.0.0.2", 7000));
+    ok!("[::1]:7000", tcp("::1", 7000));
+    ok!("::1", tcp("::1", 6859));
+    ok!("[fe80::1]", tcp("fe80::1", 6859));
+    ok!(
+        "unix:/run/mindustry.sock",
+        Endpoint::Unix("/run/mindustry.sock".into())
+    );
+    ok!("./console.sock", Endpoint::Unix("./console.sock".into()));
+    assert!(Endpoint::from_str("").is_err());
+    assert!(Endpoint::from_str("unix:").is_err());
+    assert!(Endpoint::from_str("localhost:port").is_err());
+    assert!(Endpoint::from_str("fe80::1:7000:x").is_err());
+    assert_eq!(tcp("::1", 7000).to_string(), "[::1]:7000");
+}
+
+#[test]
+fn framing() {
+    let mut f = Framer::default();
+    f.push(b"{\"i\":\"a");
+    assert_eq!(f.flush(false), None);
+    let (a, b) = "bc\",\"ln\":\"б".as_bytes().split_at(11);
+    f.push(a);
+    f.push(b);
+    f.push(b"\"}\r\n\x1b[31mred\x1b[0m\n");
+    assert_eq!(
+        f.flush(false).as_deref(),
+        Some("{\"i\":\"abc\",\"ln\":\"б\"}\nred")
+    );
+    assert!(f.is_empty());
+    f.push(b"> ");
+    assert_eq!(f.flush(false), None);
+    assert_eq!(f.flush(true).as_deref(), Some("> "));
+    assert!(f.is_empty());
+    f.push(&vec![b'a'; MAX_BLOCK + 5]);
+    assert_eq!(f.partial.len(), 5);
+    assert_eq!(f.flush(false).map(|x| x.len()), Some(MAX_BLOCK));
+}
+
+#[tokio::test]
+async fn linked() {
+    let fake = crate::fake::Fake::script(crate::fake::CONSOLE)
+        .spawn()
+        .await;
+    let (s, mut unasked) = fake.server();
+    let status = s.console.request("status").await.unwrap();
+    assert_eq!(status, "57 TPS / 274 MB / 2 PLAYERS");
+    fake.say("the server says hi");
+    assert_eq!(unasked.recv().await.unwrap(), "the server says hi");
+    assert_eq!(s.scrollback.tail(1)[0].text, "the server says hi");
+}
+
+#[tokio::test]
+async fn interrupted() {
+    let fake = crate::fake::Fake::script(crate::fake::CONSOLE)
+        .on(
+            "status",
+            "nile has connected. [JjvYb8x7FWSV+zA0VsuHlw==]\n57 TPS / 274 MB / 2 PLAYERS\nnile: hi",
+        )
+        .spawn()
+        .await;
+    let (s, mut unasked) = fake.server();
+    fake.say("bendn: hello");
+    let status = s.console.request("status").await.unwrap();
+    assert_eq!(status, "57 TPS / 274 MB / 2 PLAYERS");
+    // the chat and the join still go to the relay
+    let mut got = vec![];
+    while got.len() < 3 {
+        let block = unasked.recv().await.unwrap();
+        got.extend(block.lines().map(str::to_owned));
+    }
+    got.sort();
+    assert_eq!(
+        got,
+        [
+            "bendn: hello",
+            "nile has connected. [JjvYb8x7FWSV+zA0VsuHlw==]",
+            "nile: hi"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn trickle() {
+    let (inner, mut server) = tokio::io::duplex(4096);
+    let (_stdin, input) = broadcast::channel(4);
+    let output = Arc::new(Fanout::new());
+    let mut rx = output.subscribe("test", 64, crate::fanout::Overflow::Block);
+    let _link = Process {
+        inner: Box::new(inner),
+        input: Some(input),
+        output: Some(output),
+        scrollback: None,
+        route: None,
+        idle: Duration::from_millis(100),
+    }
+    .link();
+    // a line every 20ms never leaves the console quiet for 100ms
+    tokio::spawn(async move {
+        for i in 0.. {
+            if server.write_all(format!("{i}\n").as_bytes()).await.is_err() {
+                return;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+    });
+    let first = timeout(Duration::from_secs(1), rx.recv())
+        .await
+        .expect("nothing was sent while the console kept talking")
+        .unwrap();
+    assert!(first.starts_with("0\n1"), "{first}");
+}
+
+#[tokio::test]
+async fn broken() {
+    use std::io::{Error, ErrorKind::ConnectionReset};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    /// reset, whatever is done with it
+    struct Reset;
+    impl AsyncRead for Reset {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            _: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Err(Error::from(ConnectionReset)))
+        }
+    }
+    impl AsyncWrite for Reset {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            _: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Poll::Ready(Err(Error::from(ConnectionReset)))
+        }
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Err(Error::from(ConnectionReset)))
+        }
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+    let (_stdin, input) = broadcast::channel(4);
+    let output = Arc::new(Fanout::new());
+    let _rx = output.subscribe("test", 64, crate::fanout::Overflow::Block);
+    let link = Process {
+        inner: Box::new(Reset),
+        input: Some(input),
+        output: Some(output),
+        scrollback: None,
+        route: None,
+        idle: Duration::from_millis(100),
+    }
+    .link();
+    // ends, to be made again, rather than panicking
+    timeout(Duration::from_secs(1), link)
+        .await
+        .unwrap()
+        .unwrap();
+}
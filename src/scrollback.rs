@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// a console line, and when it came in.
+#[derive(Clone, Debug, PartialEq, Eq, serde_derive::Serialize)]
+pub struct Line {
+    /// unix seconds
+    pub at: u64,
+    pub text: String,
+}
+
+impl Line {
+    pub fn now(text: impl Into<String>) -> Self {
+        Self {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            text: text.into(),
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    /// `hh:mm:ss text`, in utc
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = self.at % 86400;
+        write!(
+            f,
+            "{:02}:{:02}:{:02} {}",
+            s / 3600,
+            s / 60 % 60,
+            s % 60,
+            self.text
+        )
+    }
+}
+
+/// the last few thousand console lines, so whoever shows up late can see what happened.
+#[derive(Debug)]
+pub struct Scrollback {
+    lines: Mutex<VecDeque<Line>>,
+    cap: usize,
+}
+
+impl Scrollback {
+    pub fn new(cap: usize) -> Self {
+        Self {
+            lines: Mutex::new(VecDeque::with_capacity(cap)),
+            cap,
+        }
+    }
+
+    /// remember a block of output. with a `cap` of 0, nothing is kept.
+    pub fn record(&self, block: &str) {
+        if self.cap == 0 {
+            return;
+        }
+        let mut lines = self.lines.lock().unwrap();
+        for line in block.lines() {
+            if lines.len() >= self.cap {
+                lines.pop_front();
+            }
+            lines.push_back(Line::now(line));
+        }
+    }
+
+    /// the last `n` lines
+    pub fn tail(&self, n: usize) -> Vec<Line> {
+        let lines = self.lines.lock().unwrap();
+        lines
+            .iter()
+            .skip(lines.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
+}
+
+#[test]
+fn scrollback() {
+    let back = Scrollback::new(3);
+    back.record("a\nb");
+    assert_eq!(
+        back.tail(5).iter().map(|l| &*l.text).collect::<Vec<_>>(),
+        ["a", "b"]
+    );
+    back.record("c\nd");
+    assert_eq!(
+        back.tail(2).iter().map(|l| &*l.text).collect::<Vec<_>>(),
+        ["c", "d"]
+    );
+    assert_eq!(back.tail(10).len(), 3);
+    let none = Scrollback::new(0);
+    none.record("a\nb");
+    assert!(none.tail(10).is_empty());
+    let line = Line {
+        at: 86400 + 3600 + 62,
+        text: "hi".into(),
+    };
+    assert_eq!(line.to_string(), "01:01:02 hi");
+}
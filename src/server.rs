@@ -1,27 +1,327 @@
+use crate::api::Recent;
+use crate::archive::Archive;
+use crate::auth::{Guard, Scope};
+use crate::bot::maps::{MapImage, Maps};
+use crate::bot::player::Players;
+use crate::bot::rules::Rules;
 use crate::bot::Bot;
-use crate::process::Process;
+use crate::cache::Cached;
+use crate::chat::History;
+use crate::console::{Console, Link};
+use crate::events::Event;
+use crate::fanout::{Fanout, Overflow};
+use crate::process::{Backoff, Endpoint, Process};
+use crate::scrollback::{Line, Scrollback};
+use crate::supervisor::{Launch, Supervisor};
+use crate::timelapse::Timelapse;
+use crate::view::Views;
 use axum::{
-    http::header::*,
-    response::{AppendHeaders, Html, IntoResponse},
-    routing::get,
+    body::StreamBody,
+    extract::{Path, Query},
+    http::{header::*, HeaderMap, StatusCode},
+    response::{AppendHeaders, Html, IntoResponse, Response},
+    routing::{get, post},
     Router, Server as AxumServer,
 };
 
-use std::{net::SocketAddr, sync::Arc};
-use tokio::{sync::broadcast, task::JoinHandle, time::sleep, time::Duration};
+use futures::StreamExt;
+use std::fs::read_to_string;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::time::UNIX_EPOCH;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+use tokio::sync::{broadcast, Mutex, OnceCell};
+use tokio::time::{sleep, Duration};
+
+/// how a server is reached and where it relays to, from `servers.json`.
+#[derive(serde_derive::Deserialize)]
+struct Config {
+    name: String,
+    #[serde(default = "default_console")]
+    console: Endpoint,
+    /// discord channel relayed into the game
+    channel: u64,
+    /// webhook the game is relayed to
+    webhook: String,
+    /// where `save 0` ends up
+    save: PathBuf,
+    /// run the server jar too, instead of just connecting to it
+    #[serde(default)]
+    launch: Option<Launch>,
+    /// where admin chat goes
+    #[serde(default)]
+    staff: Option<Staff>,
+}
+
+/// a private channel, talking with the admins in game.
+#[derive(Clone, serde_derive::Deserialize)]
+pub struct Staff {
+    /// relayed to the admins
+    pub channel: u64,
+    /// admin chat is relayed to
+    pub webhook: String,
+}
+
+impl Staff {
+    /// `STAFF_CHANNEL` and `STAFF_WEBHOOK`, if both are set
+    fn from_env() -> Option<Self> {
+        Some(Self {
+            channel: std::env::var("STAFF_CHANNEL")
+                .ok()?
+                .parse()
+                .expect("STAFF_CHANNEL should be a channel id"),
+            webhook: std::env::var("STAFF_WEBHOOK").ok()?,
+        })
+    }
+}
+
+fn default_console() -> Endpoint {
+    Endpoint::Tcp("localhost".into(), 6859)
+}
+
+impl Config {
+    /// reads `servers.json` (or `$SERVERS`).
+    /// without one, theres a single server, configured through the environment.
+    fn load() -> Vec<Self> {
+        let path = std::env::var("SERVERS").unwrap_or_else(|_| "servers.json".into());
+        if let Ok(x) = read_to_string(&path) {
+            let servers: Vec<Self> = serde_json::from_str(&x)
+                .unwrap_or_else(|e| panic!("{path} should be a list of servers: {e}"));
+            assert!(!servers.is_empty(), "{path} has no servers");
+            return servers;
+        }
+        vec![Self {
+            name: std::env::var("NAME").unwrap_or_else(|_| "main".into()),
+            console: std::env::var("CONSOLE").map_or_else(
+                |_| default_console(),
+                |x| {
+                    x.parse()
+                        .expect("CONSOLE should be a host:port or unix socket path")
+                },
+            ),
+            channel: crate::bot::CHANNEL,
+            webhook: std::env::var("WEBHOOK")
+                .unwrap_or_else(|_| read_to_string("webhook").expect("wher webhook")),
+            save: std::env::var("SAVE_PATH").expect("wher save").into(),
+            launch: Launch::from_env(),
+            staff: Staff::from_env(),
+        }]
+    }
+}
+
+/// console timings, shared by every server.
+#[derive(Clone, Copy)]
+struct Tuning {
+    /// `CONSOLE_IDLE`: quiet time that ends a block of output
+    idle: Duration,
+    /// `CONSOLE_TIMEOUT`: connecting
+    connect: Duration,
+    /// `CONSOLE_QUEUE`: how long commands wait for a lost console
+    queue: Duration,
+    /// `CONSOLE_REPLY`: how long requests wait for their answer
+    reply: Duration,
+    /// `SCROLLBACK`: lines kept
+    scrollback: usize,
+}
+
+impl Tuning {
+    fn from_env() -> Self {
+        let duration = |var: &str, default| {
+            std::env::var(var).map_or(default, |x| {
+                parse_duration::parse(&x)
+                    .unwrap_or_else(|e| panic!("{var} should be a duration: {e}"))
+            })
+        };
+        Self {
+            idle: duration("CONSOLE_IDLE", Duration::from_millis(100)),
+            connect: duration("CONSOLE_TIMEOUT", Duration::from_secs(5)),
+            queue: duration("CONSOLE_QUEUE", Duration::from_secs(30)),
+            reply: duration("CONSOLE_REPLY", Duration::from_secs(10)),
+            scrollback: std::env::var("SCROLLBACK").map_or(1000, |x| {
+                x.parse().expect("SCROLLBACK should be a number of lines")
+            }),
+        }
+    }
+}
 
 // its a arced arcs
+/// one mindustry server.
 pub struct State {
+    pub name: String,
     // sent from the process to the websockets
-    pub stdout: broadcast::Sender<String>,
+    pub stdout: Arc<Fanout<String>>,
     // sent to the process
-    pub stdin: broadcast::Sender<String>,
+    pub console: Console,
+    /// recent output, for `/console tail` and `/scrollback`
+    pub scrollback: Arc<Scrollback>,
+    pub channel: u64,
+    pub webhook: String,
+    pub staff: Option<Staff>,
+    pub save: PathBuf,
+    endpoint: Endpoint,
+    pub maps: Maps,
+    pub players: Players,
+    pub rules: OnceCell<Mutex<Rules>>,
+    pub map_image: MapImage,
+    /// `/view`s besides the default
+    pub views: Views,
+    /// old saves
+    pub archive: Archive,
+    /// this games frames
+    pub timelapse: Timelapse,
+    /// parsed by the relay, for `/events`
+    pub events: Fanout<Event>,
+    /// recent chat, for `/chat`
+    pub chat: History,
+    /// what `/api` and `/metrics` last got
+    pub recent: Recent,
+    /// if the panel runs the server itself
+    pub supervisor: Option<Arc<Supervisor>>,
+    /// times the console connection was lost
+    pub reconnects: AtomicU64,
+}
+
+impl State {
+    fn new(config: Config, stdin: broadcast::Sender<String>, tuning: Tuning) -> Self {
+        let supervisor = config
+            .launch
+            .map(|launch| Arc::new(Supervisor::new(config.name.clone(), launch)));
+        let archive = Archive::from_env(&config.name);
+        Self {
+            name: config.name,
+            stdout: Arc::new(Fanout::new()),
+            console: Console::new(stdin, tuning.queue, tuning.reply),
+            scrollback: Arc::new(Scrollback::new(tuning.scrollback)),
+            channel: config.channel,
+            webhook: config.webhook,
+            staff: config.staff,
+            save: config.save,
+            endpoint: config.console,
+            maps: Maps::new(),
+            players: Players::new(),
+            rules: OnceCell::const_new(),
+            map_image: MapImage::new(),
+            views: Views::new(),
+            archive,
+            timelapse: Timelapse::from_env(),
+            events: Fanout::new(),
+            chat: History::new(),
+            supervisor,
+            recent: Recent::new(),
+            reconnects: AtomicU64::new(0),
+        }
+    }
+
+    /// keeps the console connected.
+    async fn link(self: Arc<Self>, stdin: broadcast::Receiver<String>, tuning: Tuning) {
+        /// failed attempts before the console counts as down
+        const DOWN_AFTER: u32 = 3;
+        let mut backoff = Backoff::new();
+        let mut failures = 0;
+        let endpoint = &self.endpoint;
+        loop {
+            let spawn = match Process::spawn(endpoint, tuning.connect).await {
+                Ok(spawn) => spawn,
+                Err(e) => {
+                    println!("{}: {e:#}; retrying in {}s", self.name, backoff.secs());
+                    failures += 1;
+                    if failures == DOWN_AFTER {
+                        self.console.set(Link::Down);
+                    }
+                    backoff.wait().await;
+                    continue;
+                }
+            };
+            println!("{}: connected to {endpoint}", self.name);
+            backoff.reset();
+            failures = 0;
+            // subscribe before anyone is told its connected, so queued commands arent missed
+            let input = stdin.resubscribe();
+            self.console.set(Link::Connected);
+            let s = self.clone();
+            let _ = spawn
+                .input(input)
+                .route(move |block| s.console.route(block))
+                .output(self.stdout.clone())
+                .scrollback(self.scrollback.clone())
+                .idle(tuning.idle)
+                .link()
+                .await;
+            self.console.set(Link::Reconnecting);
+            self.reconnects.fetch_add(1, Relaxed);
+            println!("{}: lost {endpoint}; reconnecting", self.name);
+            sleep(Duration::from_secs(1)).await;
+        }
+    }
 }
 
+#[cfg(test)]
 impl State {
-    fn new(stdin: broadcast::Sender<String>) -> Self {
-        let (stdout, _) = broadcast::channel(2);
-        Self { stdout, stdin }
+    /// a server on a [`crate::fake::Fake`]
+    pub fn fake(console: Endpoint) -> Arc<Self> {
+        // one each, so tests (and test runs) dont share a save
+        static FAKES: AtomicU64 = AtomicU64::new(0);
+        let save = format!(
+            "panel-fake-{}-{}.msav",
+            std::process::id(),
+            FAKES.fetch_add(1, Relaxed)
+        );
+        let config = Config {
+            name: "fake".into(),
+            console,
+            channel: 0,
+            webhook: String::new(),
+            save: std::env::temp_dir().join(save),
+            launch: None,
+            staff: None,
+        };
+        let tuning = Tuning {
+            idle: Duration::from_millis(20),
+            connect: Duration::from_secs(1),
+            queue: Duration::from_secs(5),
+            reply: Duration::from_secs(5),
+            scrollback: 100,
+        };
+        let (stdin_tx, stdin) = broadcast::channel(8);
+        let state = Arc::new(Self::new(config, stdin_tx, tuning));
+        tokio::spawn(state.clone().link(stdin, tuning));
+        state
+    }
+}
+
+/// all the servers. never empty.
+pub struct Servers(Vec<Arc<State>>);
+
+impl std::fmt::Debug for Servers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|s| &s.name))
+            .finish()
+    }
+}
+
+impl Servers {
+    pub fn get(&self, name: &str) -> Option<&Arc<State>> {
+        self.0.iter().find(|s| s.name == name)
+    }
+
+    /// the server relaying to this channel
+    pub fn by_channel(&self, channel: u64) -> Option<&Arc<State>> {
+        self.0.iter().find(|s| s.channel == channel)
+    }
+
+    /// the server whose admins talk in this channel
+    pub fn by_staff_channel(&self, channel: u64) -> Option<&Arc<State>> {
+        self.0
+            .iter()
+            .find(|s| s.staff.as_ref().is_some_and(|st| st.channel == channel))
+    }
+
+    pub fn first(&self) -> &Arc<State> {
+        &self.0[0]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<State>> {
+        self.0.iter()
     }
 }
 
@@ -49,40 +349,144 @@ macro_rules! png {
     };
 }
 
-async fn map_view(
-    axum::extract::State(state): axum::extract::State<Arc<State>>,
-) -> impl IntoResponse {
-    (
-        AppendHeaders([(CONTENT_TYPE, "image/png")]),
-        crate::bot::maps::MAP_IMAGE
-            .get(&state.stdin)
-            .await
-            .unwrap()
-            .0
-            .clone(),
-    )
+/// `/view/:server`, or the first server for `/view`
+pub fn pick(servers: &Servers, server: Option<Path<String>>) -> Result<Arc<State>, StatusCode> {
+    match server {
+        Some(Path(name)) => servers.get(&name).cloned().ok_or(StatusCode::NOT_FOUND),
+        None => Ok(servers.first().clone()),
+    }
+}
+
+/// the console (or the save) isnt cooperating
+pub fn unavailable(e: anyhow::Error) -> StatusCode {
+    println!("web: {e:#}");
+    match e.downcast_ref::<crate::console::Error>() {
+        Some(crate::console::Error::Timeout { .. }) => StatusCode::GATEWAY_TIMEOUT,
+        Some(_) => StatusCode::SERVICE_UNAVAILABLE,
+        None => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// the save on disk. the etag is when it was written, so checking it doesnt need the file read.
+fn saved(state: &State) -> Option<Cached> {
+    let m = std::fs::metadata(&state.save).ok()?;
+    let modified = m.modified().ok()?;
+    let at = modified.duration_since(UNIX_EPOCH).ok()?;
+    Some(Cached {
+        etag: format!("\"{:x}-{:x}\"", at.as_nanos(), m.len()),
+        modified,
+        // it could be saved again any time, so always ask
+        control: "private, no-cache".into(),
+    })
 }
 
 async fn map_file(
-    axum::extract::State(state): axum::extract::State<Arc<State>>,
-) -> impl IntoResponse {
-    (
-        AppendHeaders([(CONTENT_TYPE, "application/octet-stream")]),
-        crate::bot::maps::savefile(&state.stdin).await.unwrap(),
-    )
+    axum::extract::State(servers): axum::extract::State<Arc<Servers>>,
+    server: Option<Path<String>>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    const KIND: &str = "application/octet-stream";
+    let state = pick(&servers, server)?;
+    // a save as recent as the map render is good enough, and saves asking the console
+    let recent = saved(&state).filter(|c| {
+        c.modified
+            .elapsed()
+            .is_ok_and(|x| x.as_secs() < crate::bot::maps::TTL)
+    });
+    let (cached, save) = match recent {
+        Some(cached) => (cached, None),
+        None => {
+            let save = crate::bot::maps::savefile(&state)
+                .await
+                .map_err(unavailable)?;
+            (
+                saved(&state).ok_or(StatusCode::SERVICE_UNAVAILABLE)?,
+                Some(save),
+            )
+        }
+    };
+    if cached.fresh(&headers) {
+        return Ok(cached.reply(&headers, KIND, Vec::new, true));
+    }
+    let save = match save {
+        Some(save) => save,
+        None => std::fs::read(&state.save).map_err(|e| unavailable(e.into()))?,
+    };
+    Ok(cached.reply(&headers, KIND, || save, true))
+}
+
+#[derive(serde_derive::Deserialize)]
+struct Tail {
+    /// lines of scrollback
+    #[serde(default = "default_tail")]
+    n: usize,
+    /// keep streaming output as it comes
+    #[serde(default)]
+    follow: bool,
+}
+
+fn default_tail() -> usize {
+    100
+}
+
+/// `/scrollback?n=100&follow=true`: recent console lines, as text.
+async fn scrollback(
+    axum::extract::State(servers): axum::extract::State<Arc<Servers>>,
+    server: Option<Path<String>>,
+    Query(tail): Query<Tail>,
+) -> Result<Response, StatusCode> {
+    let state = pick(&servers, server)?;
+    let text = |lines: Vec<Line>| {
+        lines.iter().fold(String::new(), |mut s, l| {
+            use std::fmt::Write;
+            writeln!(s, "{l}").unwrap();
+            s
+        })
+    };
+    let plain = AppendHeaders([(CONTENT_TYPE, "text/plain; charset=utf-8")]);
+    if !tail.follow {
+        return Ok((plain, text(state.scrollback.tail(tail.n))).into_response());
+    }
+    // a reader that cant keep up gets cut off, rather than holding up everyone else
+    let (lines, rx) = state
+        .stdout
+        .subscribe_with("scrollback", 256, Overflow::Disconnect, || {
+            state.scrollback.tail(tail.n)
+        });
+    let live = futures::stream::unfold(rx, move |mut rx| async move {
+        let block = rx.recv().await?;
+        Some((text(block.lines().map(Line::now).collect()), rx))
+    });
+    let body = futures::stream::once(async move { text(lines) })
+        .chain(live)
+        .map(Ok::<_, std::convert::Infallible>);
+    Ok((plain, StreamBody::new(body)).into_response())
 }
 
 pub struct Server;
 impl Server {
     pub async fn spawn(addr: SocketAddr) {
-        let (stdin_tx, stdin) = broadcast::channel(8);
-        let state = Arc::new(State::new(stdin_tx));
+        let tuning = Tuning::from_env();
+        let mut servers = vec![];
+        let mut links = vec![];
+        for config in Config::load() {
+            let (stdin_tx, stdin) = broadcast::channel(8);
+            let state = Arc::new(State::new(config, stdin_tx, tuning));
+            if let Some(supervisor) = &state.supervisor {
+                tokio::spawn(supervisor.clone().run());
+            }
+            tokio::spawn(crate::archive::run(state.clone()));
+            tokio::spawn(crate::timelapse::run(state.clone()));
+            links.push(state.clone().link(stdin, tuning));
+            servers.push(state);
+        }
+        let servers = Arc::new(Servers(servers));
         let router = Router::new()
             .route("/", html!(index))
             .route("/plaguess.png", png!(plaguess))
             .route("/favicon.ico", png!(logo32))
-            .route("/view", get(map_view))
-            .route("/savefile", get(map_file))
+            .route("/view", get(crate::view::view))
+            .route("/view/:server", get(crate::view::view))
             .route(
                 "/masm_bg.wasm",
                 get(|| async {
@@ -109,45 +513,47 @@ impl Server {
                 }),
             )
             .route("/viewer", html!(viewer))
-            .with_state(state.clone());
+            .route("/console", html!(console))
+            .route("/login", html!(login).post(crate::auth::login))
+            // not a get, so a link or image elsewhere cant log anyone out
+            .route("/logout", post(crate::auth::logout))
+            .route("/avatar/:id", get(crate::avatars::avatar))
+            .route("/events", get(crate::events::events))
+            .route("/events/:server", get(crate::events::events))
+            // checks for itself, since the token comes after the upgrade
+            .route("/console/ws", get(crate::live::console))
+            .route("/console/ws/:server", get(crate::live::console))
+            .route("/chat", html!(chat))
+            .route("/chat/feed", get(crate::chat::feed))
+            .route("/chat/feed/:server", get(crate::chat::feed))
+            .route("/chat/say", post(crate::chat::say))
+            .route("/chat/say/:server", post(crate::chat::say))
+            .merge(crate::api::router())
+            .merge(crate::archive::router())
+            .merge(
+                Router::new()
+                    .route("/metrics", get(crate::metrics::metrics))
+                    // the viewer sends its login (or token) along
+                    .route("/savefile", get(map_file))
+                    .route("/savefile/:server", get(map_file))
+                    .need(Scope::Read),
+            )
+            .merge(
+                Router::new()
+                    .route("/scrollback", get(scrollback))
+                    .route("/scrollback/:server", get(scrollback))
+                    .need(Scope::Moderate),
+            )
+            .with_state(servers.clone());
         tokio::spawn(async move {
             AxumServer::bind(&addr)
-                .serve(router.into_make_service())
+                .serve(router.into_make_service_with_connect_info::<SocketAddr>())
                 .await
                 .unwrap();
         });
-        let stdout = state.stdout.clone();
-        tokio::spawn(async move {
-            let mut process_handle: Option<JoinHandle<()>> = None;
-            let mut backoff = 1u64;
-            macro_rules! backoff {
-                () => {
-                    backoff <<= 1;
-                    sleep(Duration::from_secs(backoff)).await;
-                    continue;
-                };
-            }
-            loop {
-                if let Some(h) = process_handle {
-                    let _ = h.await;
-                    process_handle = None;
-                    println!("process died; waiting {}s", backoff << 2);
-                }
-                let Ok(spawn) = Process::spawn().await else {
-                    backoff!();
-                };
-                process_handle = Some(
-                    spawn
-                        .input(stdin.resubscribe())
-                        .output(stdout.clone())
-                        .link(),
-                );
-                if backoff == 1 {
-                    continue;
-                }
-                backoff!();
-            }
-        });
-        Bot::spawn(state.stdout.subscribe(), state.stdin.clone()).await;
+        for link in links {
+            tokio::spawn(link);
+        }
+        Bot::spawn(servers).await;
     }
 }
@@ -0,0 +1,237 @@
+use crate::process::Backoff;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::watch;
+use tokio::time::{timeout, Instant};
+
+/// lines of output kept for `/server`
+const TAIL: usize = 50;
+/// a server that stayed up this long didnt crash on startup, so the backoff starts over
+const STABLE: Duration = Duration::from_secs(60);
+/// how long `exit` gets before the jvm is killed
+const GRACE: Duration = Duration::from_secs(15);
+
+/// how to start a server, from the `launch` entry in `servers.json`.
+#[derive(Clone, Debug, serde_derive::Deserialize)]
+pub struct Launch {
+    #[serde(default = "java")]
+    pub java: String,
+    /// jvm flags, like `-Xmx2G`
+    #[serde(default)]
+    pub flags: Vec<String>,
+    pub jar: PathBuf,
+    /// working directory (where `config/` ends up)
+    pub dir: Option<PathBuf>,
+}
+
+fn java() -> String {
+    "java".into()
+}
+
+impl Launch {
+    /// `JAR`, `JAVA`, `JAVA_FLAGS` and `SERVER_DIR`, if `JAR` is set.
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            jar: std::env::var("JAR").ok()?.into(),
+            java: std::env::var("JAVA").unwrap_or_else(|_| java()),
+            flags: std::env::var("JAVA_FLAGS").map_or(vec![], |x| {
+                x.split_whitespace().map(str::to_owned).collect()
+            }),
+            dir: std::env::var("SERVER_DIR").ok().map(PathBuf::from),
+        })
+    }
+
+    fn command(&self) -> Command {
+        let mut c = Command::new(&self.java);
+        c.args(&self.flags)
+            .arg("-jar")
+            .arg(&self.jar)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(dir) = &self.dir {
+            c.current_dir(dir);
+        }
+        c
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Run {
+    /// times the server has been started
+    pub starts: u64,
+    pub running: bool,
+    /// how it exited last
+    pub exit: Option<ExitStatus>,
+}
+
+/// runs the server jar, and restarts it when it dies.
+pub struct Supervisor {
+    name: String,
+    launch: Launch,
+    /// whether the server should be up
+    wanted: watch::Sender<bool>,
+    /// bumped to ask for a restart
+    restarts: watch::Sender<u64>,
+    run: watch::Sender<Run>,
+    tail: Mutex<VecDeque<String>>,
+}
+
+impl Supervisor {
+    pub fn new(name: String, launch: Launch) -> Self {
+        Self {
+            name,
+            launch,
+            wanted: watch::channel(true).0,
+            restarts: watch::channel(0).0,
+            run: watch::channel(Run::default()).0,
+            tail: Mutex::new(VecDeque::with_capacity(TAIL)),
+        }
+    }
+
+    pub fn status(&self) -> Run {
+        *self.run.borrow()
+    }
+
+    /// the last `n` lines the jvm printed
+    pub fn tail(&self, n: usize) -> Vec<String> {
+        let tail = self.tail.lock().unwrap();
+        tail.iter()
+            .skip(tail.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
+
+    /// start the server, if its stopped. waits for it to be up.
+    pub async fn start(&self) -> Run {
+        self.wanted.send_replace(true);
+        self.wait(|r| r.running).await
+    }
+
+    /// stop the server, and keep it stopped. waits for it to exit.
+    pub async fn stop(&self) -> Run {
+        self.wanted.send_replace(false);
+        self.wait(|r| !r.running).await
+    }
+
+    /// stop the server, then start it again.
+    pub async fn restart(&self) -> Run {
+        let starts = self.status().starts;
+        self.wanted.send_replace(true);
+        self.restarts.send_modify(|n| *n += 1);
+        self.wait(|r| r.running && r.starts > starts).await
+    }
+
+    async fn wait(&self, done: impl Fn(&Run) -> bool) -> Run {
+        let mut run = self.run.subscribe();
+        // dont hang the command forever if the jvm wont budge
+        let _ = timeout(GRACE * 4, run.wait_for(done)).await;
+        let now = *run.borrow();
+        now
+    }
+
+    fn line(&self, line: String) {
+        let mut tail = self.tail.lock().unwrap();
+        if tail.len() == TAIL {
+            tail.pop_front();
+        }
+        tail.push_back(line);
+    }
+
+    /// collects the output of the jvm into the tail.
+    fn capture(self: &Arc<Self>, from: Option<impl AsyncRead + Unpin + Send + 'static>) {
+        let Some(from) = from else { return };
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut from = BufReader::new(from);
+            let mut buf = vec![];
+            while from.read_until(b'\n', &mut buf).await.is_ok_and(|n| n != 0) {
+                let line = strip_ansi_escapes::strip(&buf);
+                buf.clear();
+                this.line(String::from_utf8_lossy(&line).trim_end().to_owned());
+            }
+        });
+    }
+
+    /// asks the server to `exit`, and kills it if it doesnt.
+    async fn shut(child: &mut Child, stdin: Option<ChildStdin>) -> std::io::Result<ExitStatus> {
+        if let Some(mut stdin) = stdin {
+            let _ = stdin.write_all(b"exit\n").await;
+            let _ = stdin.flush().await;
+        }
+        if let Ok(status) = timeout(GRACE, child.wait()).await {
+            return status;
+        }
+        child.kill().await?;
+        child.wait().await
+    }
+
+    /// keeps the server running while its wanted.
+    pub async fn run(self: Arc<Self>) {
+        let name = &self.name;
+        let mut wanted = self.wanted.subscribe();
+        let mut restarts = self.restarts.subscribe();
+        let mut backoff = Backoff::new();
+        loop {
+            if wanted.wait_for(|&w| w).await.is_err() {
+                return;
+            }
+            // a restart asked for while stopped is just a start
+            restarts.borrow_and_update();
+            let mut child = match self.launch.command().spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    println!(
+                        "{name}: cant run {}: {e}; retrying in {}s",
+                        self.launch.java,
+                        backoff.secs()
+                    );
+                    self.line(format!("failed to start: {e}"));
+                    backoff.wait().await;
+                    continue;
+                }
+            };
+            let stdin = child.stdin.take();
+            self.capture(child.stdout.take());
+            self.capture(child.stderr.take());
+            self.run.send_modify(|r| {
+                r.starts += 1;
+                r.running = true;
+            });
+            println!("{name}: started {}", self.launch.jar.display());
+            let started = Instant::now();
+            let exited = tokio::select! {
+                status = child.wait() => Some(status),
+                _ = wanted.wait_for(|&w| !w) => None,
+                _ = restarts.changed() => None,
+            };
+            let asked = exited.is_none();
+            let status = match exited {
+                Some(status) => status,
+                None => Self::shut(&mut child, stdin).await,
+            }
+            .ok();
+            self.run.send_modify(|r| {
+                r.running = false;
+                r.exit = status;
+            });
+            let status = status.map_or("unknown status".into(), |s| s.to_string());
+            if asked {
+                println!("{name}: stopped ({status})");
+                backoff.reset();
+                continue;
+            }
+            if started.elapsed() > STABLE {
+                backoff.reset();
+            }
+            println!("{name}: died ({status}); restarting in {}s", backoff.secs());
+            backoff.wait().await;
+        }
+    }
+}
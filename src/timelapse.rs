@@ -0,0 +1,193 @@
+//! a gif of each game: a frame every so often, posted to the relay when the map changes.
+use crate::server::State;
+use crate::view::{scale, Raw};
+use gif::{Encoder, Frame, Repeat};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// the longest side of a frame, in pixels
+const SIDE: u32 = 400;
+/// frames kept. past this (or [`BYTES`]), every other one is dropped
+const FRAMES: usize = 100;
+/// so it fits in a discord upload
+const BYTES: usize = 7 * 1024 * 1024;
+/// hundredths of a second per frame
+const DELAY: u16 = 20;
+/// how long the last frame stays up
+const END: u16 = 300;
+
+/// the frames of the game in progress.
+struct Game {
+    frames: Vec<Frame<'static>>,
+    /// only every `stride`th frame is kept, so long games still fit
+    stride: u32,
+    seen: u32,
+}
+
+impl Game {
+    const fn new() -> Self {
+        Self {
+            frames: vec![],
+            stride: 1,
+            seen: 0,
+        }
+    }
+
+    fn push(&mut self, frame: Frame<'static>) {
+        // a different size is a different map, even if the load was missed
+        if let Some(first) = self.frames.first()
+            && (first.width, first.height) != (frame.width, frame.height)
+        {
+            *self = Self::new();
+        }
+        self.seen += 1;
+        if self.seen % self.stride != 0 {
+            return;
+        }
+        self.frames.push(frame);
+        if self.frames.len() >= FRAMES
+            || self.frames.iter().map(|f| f.buffer.len()).sum::<usize>() > BYTES
+        {
+            let mut i = 0;
+            self.frames.retain(|_| {
+                i += 1;
+                i % 2 == 1
+            });
+            self.stride *= 2;
+        }
+    }
+
+    /// none for less than two frames
+    fn gif(&self) -> Option<Vec<u8>> {
+        let [first, .., last] = &self.frames[..] else {
+            return None;
+        };
+        let mut e = Encoder::new(vec![], first.width, first.height, &[]).ok()?;
+        e.set_repeat(Repeat::Infinite).ok()?;
+        for f in &self.frames[..self.frames.len() - 1] {
+            e.write_lzw_pre_encoded_frame(f).ok()?;
+        }
+        let mut last = last.clone();
+        last.delay = END;
+        e.write_lzw_pre_encoded_frame(&last).ok()?;
+        e.into_inner().ok()
+    }
+}
+
+/// one servers timelapse.
+pub struct Timelapse {
+    /// `TIMELAPSE_EVERY`: time between frames, zero for none
+    every: Duration,
+    game: Mutex<Game>,
+}
+
+impl Timelapse {
+    pub fn from_env() -> Self {
+        Self::new(
+            std::env::var("TIMELAPSE_EVERY").map_or(Duration::from_secs(2 * 60), |x| {
+                parse_duration::parse(&x)
+                    .unwrap_or_else(|e| panic!("TIMELAPSE_EVERY should be a duration: {e}"))
+            }),
+        )
+    }
+
+    const fn new(every: Duration) -> Self {
+        Self {
+            every,
+            game: Mutex::new(Game::new()),
+        }
+    }
+
+    /// shrink and quantize a render. blocks.
+    fn frame(raw: &Raw) -> Frame<'static> {
+        let (width, height) = if raw.width.max(raw.height) > SIDE {
+            let big = raw.width.max(raw.height);
+            (
+                (raw.width * SIDE / big).max(1),
+                (raw.height * SIDE / big).max(1),
+            )
+        } else {
+            (raw.width, raw.height)
+        };
+        let small = scale(raw, width, height);
+        let mut f = Frame::from_rgb_speed(width as u16, height as u16, &small.rgb, 10);
+        f.delay = DELAY;
+        // compressed now, so a game of frames isnt too big to keep
+        f.make_lzw_pre_encoded();
+        f
+    }
+
+    /// the game so far
+    pub fn gif(&self) -> Option<Vec<u8>> {
+        self.game.lock().unwrap().gif()
+    }
+
+    /// the game so far, and start a new one
+    pub fn finish(&self) -> Option<Vec<u8>> {
+        std::mem::replace(&mut *self.game.lock().unwrap(), Game::new()).gif()
+    }
+}
+
+/// take frames of `s` forever.
+pub async fn run(s: Arc<State>) {
+    define_print!("timelapse");
+    let name = &s.name;
+    if s.timelapse.every.is_zero() {
+        return;
+    }
+    let mut every = tokio::time::interval(s.timelapse.every);
+    every.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        every.tick().await;
+        // shares renders with `/view`
+        let layers = match s.map_image.get(&s).await {
+            Ok((i, _)) => i.layers.clone(),
+            Err(e) => {
+                output!("{name}: no frame: {e:#}");
+                continue;
+            }
+        };
+        let Some(layers) = layers else {
+            continue;
+        };
+        let state = s.clone();
+        tokio::task::spawn_blocking(move || {
+            let frame = Timelapse::frame(&layers.units);
+            state.timelapse.game.lock().unwrap().push(frame);
+        })
+        .await
+        .unwrap();
+    }
+}
+
+#[test]
+fn timelapse() {
+    let raw = |width, height, shade| Raw {
+        width,
+        height,
+        rgb: vec![shade; width as usize * height as usize * 3],
+    };
+    let t = Timelapse::new(Duration::from_secs(60));
+    assert!(t.gif().is_none());
+    let f = Timelapse::frame(&raw(800, 200, 0));
+    assert_eq!((f.width, f.height), (400, 100));
+    t.game.lock().unwrap().push(f);
+    assert!(t.gif().is_none());
+    for shade in 1..FRAMES as u8 + 10 {
+        t.game
+            .lock()
+            .unwrap()
+            .push(Timelapse::frame(&raw(80, 20, shade)));
+    }
+    {
+        // the big frame was another map
+        let game = t.game.lock().unwrap();
+        assert!(game.frames.len() < FRAMES);
+        assert_eq!(game.stride, 2);
+        assert!(game.frames.iter().all(|f| f.width == 80));
+    }
+    let gif = t.finish().unwrap();
+    assert!(gif.starts_with(b"GIF89a"));
+    assert_eq!(gif.last(), Some(&0x3b));
+    assert!(t.gif().is_none());
+}
@@ -0,0 +1,506 @@
+//! `/view?scale=&x=&y=&w=&h=&units=&format=&grid=`: the map, zoomed in (or out), cropped,
+//! with or without units, as png, jpeg or webp. the full render stays the default.
+use crate::auth::{Access, Scope};
+use crate::bot::maps::TTL;
+use crate::cache::Cached;
+use crate::server::{pick, unavailable, Servers, State};
+use axum::{
+    extract::{Path, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use oxipng::*;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
+
+/// the biggest image made, per side
+const MAX: u32 = 4096;
+/// the biggest image made for anyone without `read`, per side
+const PUBLIC: u32 = 1024;
+/// views made at once, so asking for many cant take every core
+const MAKING: usize = 2;
+/// grid lines are this many pixels of the full render apart
+const GRID: u32 = 50;
+/// views kept per render
+const KEEP: usize = 64;
+
+/// theres nothing to make a view of yet
+#[derive(Debug)]
+struct Unrendered;
+
+impl std::fmt::Display for Unrendered {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the map hasnt been rendered yet")
+    }
+}
+
+impl std::error::Error for Unrendered {}
+
+/// rgb pixels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Raw {
+    pub width: u32,
+    pub height: u32,
+    pub rgb: Vec<u8>,
+}
+
+/// a render, with and without units
+pub struct Layers {
+    pub units: Raw,
+    pub bare: Raw,
+}
+
+/// fast, since it happens every render
+pub fn png(raw: &Raw) -> Vec<u8> {
+    RawImage::new(
+        raw.width,
+        raw.height,
+        ColorType::RGB {
+            transparent_color: None,
+        },
+        BitDepth::Eight,
+        raw.rgb.clone(),
+    )
+    .unwrap()
+    .create_optimized_png(&oxipng::Options {
+        filter: indexset! { RowFilter::None },
+        bit_depth_reduction: false,
+        color_type_reduction: false,
+        palette_reduction: false,
+        grayscale_reduction: false,
+        ..oxipng::Options::from_preset(0)
+    })
+    .unwrap()
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, serde_derive::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Png,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Webp,
+}
+
+impl Format {
+    fn mime(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+        }
+    }
+}
+
+/// the query, before its checked.
+#[derive(Default, serde_derive::Deserialize)]
+pub struct Params {
+    /// 0.1 to 8
+    scale: Option<f32>,
+    /// the crop, in pixels of the full render
+    x: Option<u32>,
+    y: Option<u32>,
+    w: Option<u32>,
+    h: Option<u32>,
+    /// default true
+    units: Option<bool>,
+    format: Option<Format>,
+    grid: Option<bool>,
+}
+
+/// a way of looking at the map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct View {
+    /// percent
+    scale: u32,
+    /// x, y, w, h
+    crop: Option<[u32; 4]>,
+    units: bool,
+    format: Format,
+    grid: bool,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        Self {
+            scale: 100,
+            crop: None,
+            units: true,
+            format: Format::Png,
+            grid: false,
+        }
+    }
+}
+
+impl Params {
+    pub fn view(self) -> Result<View, String> {
+        let scale = self.scale.unwrap_or(1.0);
+        if !(0.1..=8.0).contains(&scale) {
+            return Err(format!("scale {scale} isnt between 0.1 and 8"));
+        }
+        let crop = match (self.x, self.y, self.w, self.h) {
+            (None, None, None, None) => None,
+            (Some(_), Some(_), Some(0), _) | (Some(_), Some(_), _, Some(0)) => {
+                return Err("cant crop to nothing".into())
+            }
+            (Some(x), Some(y), Some(w), Some(h)) => Some([x, y, w, h]),
+            _ => return Err("crop needs all of x, y, w and h".into()),
+        };
+        Ok(View {
+            scale: (scale * 100.0).round() as u32,
+            crop,
+            units: self.units.unwrap_or(true),
+            format: self.format.unwrap_or_default(),
+            grid: self.grid.unwrap_or(false),
+        })
+    }
+}
+
+/// nearest neighbour, so blocks stay crisp
+pub fn scale(raw: &Raw, width: u32, height: u32) -> Raw {
+    let mut rgb = Vec::with_capacity(width as usize * height as usize * 3);
+    for y in 0..height {
+        let sy = (u64::from(y) * u64::from(raw.height) / u64::from(height)) as usize;
+        for x in 0..width {
+            let sx = (u64::from(x) * u64::from(raw.width) / u64::from(width)) as usize;
+            let at = (sy * raw.width as usize + sx) * 3;
+            rgb.extend_from_slice(&raw.rgb[at..at + 3]);
+        }
+    }
+    Raw { width, height, rgb }
+}
+
+impl View {
+    /// the image, at most `max` a side. errors are the clients fault.
+    fn apply(&self, layers: &Layers, max: u32) -> Result<Raw, String> {
+        let full = if self.units {
+            &layers.units
+        } else {
+            &layers.bare
+        };
+        let [x, y, w, h] = self.crop.unwrap_or([0, 0, full.width, full.height]);
+        if x.saturating_add(w) > full.width || y.saturating_add(h) > full.height {
+            return Err(format!(
+                "{w}x{h} at {x},{y} doesnt fit in {}x{}",
+                full.width, full.height
+            ));
+        }
+        let (ow, oh) = (
+            (w * self.scale).div_ceil(100).max(1),
+            (h * self.scale).div_ceil(100).max(1),
+        );
+        if ow > max || oh > max {
+            let more = if max < MAX {
+                format!(" (log in for up to {MAX}x{MAX})")
+            } else {
+                String::new()
+            };
+            return Err(format!("{ow}x{oh} is bigger than {max}x{max}{more}"));
+        }
+        let mut rgb = Vec::with_capacity(w as usize * h as usize * 3);
+        for row in y..y + h {
+            let at = (row as usize * full.width as usize + x as usize) * 3;
+            rgb.extend_from_slice(&full.rgb[at..at + w as usize * 3]);
+        }
+        let mut out = scale(
+            &Raw {
+                width: w,
+                height: h,
+                rgb,
+            },
+            ow,
+            oh,
+        );
+        if self.grid {
+            // lines where the full render crosses a multiple of GRID, so theyre the same across crops
+            let line = |o: u32, from: u32, size: u32, out: u32| {
+                let src = |o: u32| from + (u64::from(o) * u64::from(size) / u64::from(out)) as u32;
+                src(o) % GRID == 0 && (o == 0 || src(o - 1) % GRID != 0)
+            };
+            for oy in 0..oh {
+                let across = line(oy, y, h, oh);
+                for ox in 0..ow {
+                    if across || line(ox, x, w, ow) {
+                        let at = (oy as usize * ow as usize + ox as usize) * 3;
+                        for c in &mut out.rgb[at..at + 3] {
+                            *c = *c / 2 + 128;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn encode(&self, raw: &Raw) -> Vec<u8> {
+        let mut out = vec![];
+        match self.format {
+            Format::Png => return png(raw),
+            Format::Jpeg => jpeg_encoder::Encoder::new(&mut out, 85)
+                .encode(
+                    &raw.rgb,
+                    raw.width as u16,
+                    raw.height as u16,
+                    jpeg_encoder::ColorType::Rgb,
+                )
+                .unwrap(),
+            Format::Webp => image_webp::WebPEncoder::new(&mut out)
+                .encode(&raw.rgb, raw.width, raw.height, image_webp::ColorType::Rgb8)
+                .unwrap(),
+        }
+        out
+    }
+}
+
+/// views made from one render
+#[derive(Default)]
+struct Made {
+    /// when the render was made
+    at: u64,
+    /// encoded images, by view, with when they were last asked for
+    views: BTreeMap<View, (Arc<Vec<u8>>, u64)>,
+    /// counts asks, for `views`
+    asks: u64,
+}
+
+impl Made {
+    fn get(&mut self, view: &View) -> Option<Arc<Vec<u8>>> {
+        self.asks += 1;
+        let (made, asked) = self.views.get_mut(view)?;
+        *asked = self.asks;
+        Some(made.clone())
+    }
+
+    /// keep `made`, forgetting the least recently asked for when theres too many
+    fn keep(&mut self, view: View, made: Arc<Vec<u8>>) {
+        if self.views.len() >= KEEP
+            && let Some(oldest) = self
+                .views
+                .iter()
+                .min_by_key(|(_, (_, asked))| *asked)
+                .map(|(v, _)| *v)
+        {
+            self.views.remove(&oldest);
+        }
+        self.asks += 1;
+        self.views.insert(view, (made, self.asks));
+    }
+}
+
+/// views made from the current render.
+pub struct Views {
+    made: std::sync::Mutex<Made>,
+    making: Semaphore,
+}
+
+impl Views {
+    pub fn new() -> Self {
+        Self {
+            made: std::sync::Mutex::default(),
+            making: Semaphore::new(MAKING),
+        }
+    }
+
+    /// the image, and when its render was made. the outer error is the servers fault, the inner the clients.
+    async fn get(
+        &self,
+        s: &State,
+        view: View,
+        max: u32,
+    ) -> anyhow::Result<Result<(Arc<Vec<u8>>, u64), String>> {
+        let (image, _) = s.map_image.get(s).await?;
+        let at = image.at;
+        let Some(layers) = image.layers.clone() else {
+            return Err(Unrendered.into());
+        };
+        if view == View::default() {
+            return Ok(Ok((Arc::new(image.png.clone()), at)));
+        }
+        drop(image);
+        {
+            let mut made = self.made.lock().unwrap();
+            if made.at != at {
+                *made = Made {
+                    at,
+                    ..Made::default()
+                };
+            }
+            if let Some(made) = made.get(&view) {
+                return Ok(Ok((made, at)));
+            }
+        }
+        // not holding `made`, so views already made dont wait for this one
+        let _making = self.making.acquire().await?;
+        let made = tokio::task::spawn_blocking(move || {
+            view.apply(&layers, max)
+                .map(|raw| Arc::new(view.encode(&raw)))
+        })
+        .await?;
+        if let Ok(image) = &made {
+            let mut made = self.made.lock().unwrap();
+            // unless theres been a new render meanwhile
+            if made.at == at {
+                made.keep(view, image.clone());
+            }
+        }
+        Ok(made.map(|made| (made, at)))
+    }
+}
+
+/// `/view/:server`
+pub async fn view(
+    axum::extract::State(servers): axum::extract::State<Arc<Servers>>,
+    server: Option<Path<String>>,
+    Query(params): Query<Params>,
+    access: Access,
+    headers: HeaderMap,
+) -> Result<Response, Response> {
+    let bad = |e| (StatusCode::BAD_REQUEST, e).into_response();
+    let state = pick(&servers, server).map_err(IntoResponse::into_response)?;
+    let view = params.view().map_err(bad)?;
+    let (image, at) = state
+        .views
+        .get(
+            &state,
+            view,
+            if access.can(Scope::Read) { MAX } else { PUBLIC },
+        )
+        .await
+        .map_err(|e| {
+            if e.is::<Unrendered>() {
+                StatusCode::SERVICE_UNAVAILABLE.into_response()
+            } else {
+                unavailable(e).into_response()
+            }
+        })?
+        .map_err(bad)?;
+    let age = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        .saturating_sub(at);
+    let cached = Cached {
+        etag: format!("\"{at}\""),
+        modified: UNIX_EPOCH + Duration::from_secs(at),
+        // until the next render
+        control: format!("public, max-age={}", TTL.saturating_sub(age)),
+    };
+    Ok(cached.reply(&headers, view.format.mime(), || (*image).clone(), false))
+}
+
+#[test]
+fn views() {
+    let params = |q: &str| {
+        let Query(p) =
+            Query::<Params>::try_from_uri(&format!("/view?{q}").parse().unwrap()).unwrap();
+        p.view()
+    };
+    assert_eq!(params(""), Ok(View::default()));
+    assert!(params("scale=9").is_err());
+    assert!(params("x=1&y=1&w=2").is_err());
+    assert!(params("x=1&y=1&w=0&h=2").is_err());
+    let view = params("x=1&y=1&w=2&h=2&scale=2&format=jpg&units=false").unwrap();
+
+    // a 4x4 image, every pixel its own index
+    let raw = Raw {
+        width: 4,
+        height: 4,
+        rgb: (0..16).flat_map(|i| [i, i, i]).collect(),
+    };
+    let layers = Layers {
+        units: Raw {
+            rgb: vec![255; 48],
+            ..raw.clone()
+        },
+        bare: raw,
+    };
+    let out = view.apply(&layers, MAX).unwrap();
+    // smaller, without a login
+    assert!(view.apply(&layers, 2).unwrap_err().contains("log in"));
+    assert_eq!((out.width, out.height), (4, 4));
+    let px = |x: usize, y: usize| out.rgb[(y * 4 + x) * 3];
+    assert_eq!([px(0, 0), px(1, 1), px(2, 0), px(3, 3)], [5, 5, 6, 10]);
+    assert!(params("x=3&y=3&w=2&h=2")
+        .unwrap()
+        .apply(&layers, MAX)
+        .is_err());
+
+    assert_eq!(&view.encode(&out)[..2], [0xff, 0xd8]);
+    let webp = params("format=webp").unwrap().encode(&out);
+    assert_eq!((&webp[..4], &webp[8..12]), (&b"RIFF"[..], &b"WEBP"[..]));
+    assert_eq!(&params("").unwrap().encode(&out)[1..4], b"PNG");
+}
+
+#[test]
+fn grid() {
+    let raw = Raw {
+        width: 120,
+        height: 1,
+        rgb: vec![0; 360],
+    };
+    let layers = Layers {
+        units: raw.clone(),
+        bare: raw,
+    };
+    let view = Params {
+        grid: Some(true),
+        x: Some(40),
+        y: Some(0),
+        w: Some(80),
+        h: Some(1),
+        ..Params::default()
+    };
+    let out = view.view().unwrap().apply(&layers, MAX).unwrap();
+    let lit = (0..80).filter(|&x| out.rgb[x * 3] != 0).collect::<Vec<_>>();
+    // the whole first row, since y=0 is a line
+    assert_eq!(lit.len(), 80);
+    let view = Params {
+        grid: Some(true),
+        x: Some(40),
+        y: Some(1),
+        w: Some(80),
+        h: Some(1),
+        ..Params::default()
+    };
+    let layers = Layers {
+        units: Raw {
+            width: 120,
+            height: 2,
+            rgb: vec![0; 720],
+        },
+        bare: Raw {
+            width: 0,
+            height: 0,
+            rgb: vec![],
+        },
+    };
+    let out = view.view().unwrap().apply(&layers, MAX).unwrap();
+    let lit = (0..80).filter(|&x| out.rgb[x * 3] != 0).collect::<Vec<_>>();
+    // 50 and 100, from 40
+    assert_eq!(lit, [10, 60]);
+}
+
+#[test]
+fn lru() {
+    let view = |scale| View {
+        scale,
+        ..View::default()
+    };
+    let mut made = Made::default();
+    for scale in 1..=KEEP as u32 {
+        made.keep(view(scale), Arc::new(vec![]));
+    }
+    // asked for again, so its kept over the others
+    assert!(made.get(&view(1)).is_some());
+    made.keep(view(800), Arc::new(vec![]));
+    assert_eq!(made.views.len(), KEEP);
+    assert!(made.get(&view(1)).is_some());
+    assert!(made.get(&view(2)).is_none());
+    assert!(made.get(&view(3)).is_some());
+    assert!(made.get(&view(800)).is_some());
+}
@@ -1,142 +1,476 @@
 use poise::serenity_prelude::{Webhook as RealHook, *};
 use regex::Regex;
-use serenity::{builder::ExecuteWebhook, http::Http};
-use std::convert::AsRef;
-use std::sync::{
-    atomic::{AtomicU8, Ordering},
-    Arc, LazyLock,
+use serenity::{
+    builder::ExecuteWebhook,
+    http::{Http, HttpError, StatusCode},
 };
-use tokio::sync::broadcast::{self, error::TryRecvError};
-use tokio::time::{sleep, Duration};
+use std::collections::{HashSet, VecDeque};
+use std::convert::AsRef;
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+use tokio::sync::mpsc;
 
+use crate::avatars::AVATARS;
 use crate::bot::strip_colors;
+use crate::console::Link;
+use crate::events::Event;
+use crate::fanout::Subscriber;
+use crate::mentions::MENTIONS;
+use crate::process::Backoff;
+use crate::server::State;
+
+/// messages waiting for discord, per webhook
+const QUEUE: usize = 256;
+/// attempts at a message before giving up on it
+const TRIES: u32 = 5;
+/// discords limit
+const LONGEST: usize = 2000;
+
+/// something waiting to be relayed
+#[derive(Debug)]
+enum Out {
+    Said {
+        username: String,
+        content: String,
+    },
+    Join(String),
+    Left(String),
+    /// embeds and files, sent as they are
+    Other(Box<ExecuteWebhook>),
+}
+
+/// the front of `pending`, with whatever after it can go along:
+/// lines from the same player, or a run of joins and leaves.
+fn batch(pending: &mut VecDeque<Out>) -> Option<Out> {
+    match pending.pop_front()? {
+        Out::Said {
+            username,
+            mut content,
+        } => {
+            while let Some(Out::Said {
+                username: next,
+                content: more,
+            }) = pending.front()
+                && *next == username
+                && content.len() + 1 + more.len() <= LONGEST
+            {
+                content.push('\n');
+                content.push_str(more);
+                pending.pop_front();
+            }
+            Some(Out::Said { username, content })
+        }
+        first @ (Out::Join(_) | Out::Left(_)) => {
+            if !matches!(pending.front(), Some(Out::Join(_) | Out::Left(_))) {
+                return Some(first);
+            }
+            // runs of the same kind, so who left before who joined stays that way
+            let mut runs: Vec<(bool, Vec<String>)> = vec![];
+            let mut next = Some(first);
+            while let Some(out) = next {
+                let (joined, p) = match out {
+                    Out::Join(p) => (true, p),
+                    Out::Left(p) => (false, p),
+                    _ => unreachable!(),
+                };
+                // names are the players to pick
+                let p = crate::mentions::escape(&p);
+                match runs.last_mut() {
+                    Some((kind, who)) if *kind == joined => who.push(p),
+                    _ => runs.push((joined, vec![p])),
+                }
+                next = match pending.front() {
+                    Some(Out::Join(_) | Out::Left(_)) => pending.pop_front(),
+                    _ => None,
+                };
+            }
+            let content = runs
+                .into_iter()
+                .map(|(joined, who)| {
+                    let did = if joined { "joined" } else { "left" };
+                    match &*who {
+                        [one] => format!("<{one} has {did} the game>"),
+                        [rest @ .., last] => {
+                            format!("<{} and {last} have {did} the game>", rest.join(", "))
+                        }
+                        [] => unreachable!(),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            Some(Out::Said {
+                username: "server".into(),
+                content,
+            })
+        }
+        other @ Out::Other(_) => Some(other),
+    }
+}
+
+/// worth trying again
+fn transient(e: &serenity::Error) -> bool {
+    match e {
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(r)) => {
+            r.status_code == StatusCode::TOO_MANY_REQUESTS || r.status_code.is_server_error()
+        }
+        serenity::Error::Http(HttpError::Request(_)) => true,
+        _ => false,
+    }
+}
 
 pub struct Webhook<'a> {
-    pub skipped: broadcast::Sender<String>,
-    pub skip: Arc<AtomicU8>,
     inner: RealHook,
     http: &'a Http,
+    tx: mpsc::Sender<Out>,
+    rx: tokio::sync::Mutex<mpsc::Receiver<Out>>,
 }
 
 impl<'a> Webhook<'a> {
     pub async fn new(http: &'a impl AsRef<Http>, url: &str) -> Webhook<'a> {
+        let (tx, rx) = mpsc::channel(QUEUE);
         Self {
             inner: RealHook::from_url(http, url).await.unwrap(),
             http: http.as_ref(),
-            skip: Arc::new(AtomicU8::new(0)),
-            skipped: broadcast::channel(16).0,
+            tx,
+            rx: tokio::sync::Mutex::new(rx),
         }
     }
 
+    fn base() -> ExecuteWebhook {
+        ExecuteWebhook::default().allowed_mentions(MENTIONS.allowed())
+    }
+
+    /// send after whats queued, like everything else.
     pub async fn send<F>(&self, block: F)
     where
         for<'b> F: FnOnce(ExecuteWebhook) -> ExecuteWebhook,
     {
-        let execute_webhook = ExecuteWebhook::default().allowed_mentions(
-            CreateAllowedMentions::default()
-                .roles(vec![
-                    1110088946374938715,
-                    1133416252791074877,
-                    1206743548838416455,
-                    1206743639397630003,
-                ])
-                .users(vec![
-                    696196765564534825,
-                    600014432298598400,
-                    1173213085553660034,
-                ]),
-        );
-        let execute_webhook = block(execute_webhook);
-        if let Err(e) = self
-            .inner
-            .execute(self.http, false, execute_webhook.clone())
-            .await
-        {
-            println!("sending {execute_webhook:#?} got error {e}.");
+        self.queue(Out::Other(Box::new(block(Self::base())))).await;
+    }
+
+    /// ratelimits are waited out by serenity, which reads the `retry-after`.
+    /// anything else that might pass is tried again, with backoff.
+    async fn deliver(&self, execute_webhook: ExecuteWebhook) {
+        let mut backoff = Backoff::new();
+        for attempt in 1..=TRIES {
+            match self
+                .inner
+                .execute(self.http, false, execute_webhook.clone())
+                .await
+            {
+                Ok(_) => return,
+                Err(e) if attempt < TRIES && transient(&e) => {
+                    println!("webhook: {e}; retrying in {}s", backoff.secs());
+                    backoff.wait().await;
+                }
+                Err(e) => {
+                    crate::metrics::WEBHOOK_FAILURES
+                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    println!("sending {execute_webhook:#?} got error {e}.");
+                    return;
+                }
+            }
+        }
+    }
+
+    /// send after everything queued before it. waits if the queue is full, so nothing is lost:
+    /// the relay falls behind instead, and its subscription decides what gives.
+    async fn queue(&self, out: Out) {
+        // only closed if [`drain`](Self::drain) is gone, and then theres nobody to send it anyway
+        let _ = self.tx.send(out).await;
+    }
+
+    /// send whats queued, in order, forever.
+    pub async fn drain(&self) {
+        let mut rx = self.rx.lock().await;
+        let mut pending = VecDeque::new();
+        loop {
+            if pending.is_empty() {
+                let Some(out) = rx.recv().await else {
+                    return;
+                };
+                pending.push_back(out);
+            }
+            // whatever came in while the last one was sent can go along with the next
+            while pending.len() < QUEUE
+                && let Ok(out) = rx.try_recv()
+            {
+                pending.push_back(out);
+            }
+            let execute_webhook = match batch(&mut pending).unwrap() {
+                Out::Said { username, content } => self.as_player(&username).await.content(content),
+                Out::Join(player) => self
+                    .as_player(&player)
+                    .await
+                    .content("<has joined the game>"),
+                Out::Left(player) => self.as_player(&player).await.content("<has left the game>"),
+                Out::Other(w) => *w,
+            };
+            self.deliver(execute_webhook).await;
+        }
+    }
+
+    /// with the players avatar, if they have one
+    async fn as_player(&self, player: &str) -> ExecuteWebhook {
+        let w = Self::base().username(player);
+        if player == "server" {
+            return w;
+        }
+        match AVATARS.url(self.http, player).await {
+            Some(url) => w.avatar_url(url),
+            None => w,
         }
     }
 
     async fn send_message(&self, username: &str, content: &str) {
         define_print!("webhook");
         output!("{username}: {content}");
-        self.send(|m| m.username(username).content(content)).await;
+        self.queue(Out::Said {
+            username: username.into(),
+            content: content.into(),
+        })
+        .await;
     }
 
-    pub async fn link(&mut self, mut stdout: broadcast::Receiver<String>) {
+    /// relay `stdout`, with admin chat going to `staff`.
+    pub async fn link(
+        &self,
+        mut stdout: Subscriber<String>,
+        s: &State,
+        staff: Option<&Webhook<'_>>,
+    ) {
         define_print!("webhook");
+        let console = &s.console;
+        let mut dropped = 0;
+        let mut link = console.watch();
+        // only say "connected" if it was said to be gone
+        let mut gone = *link.borrow_and_update() == Link::Down;
         loop {
-            let out = stdout.try_recv();
-            match out {
-                Err(e) => match e {
-                    TryRecvError::Closed => fail!("closed"),
-                    _ => sleep(Duration::from_millis(100)).await,
-                },
-                Ok(m) => {
-                    if self
-                        .skip
-                        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
-                        .is_ok()
-                    {
-                        input!("{m} < skipped");
-                        if let Err(e) = self.skipped.send(m) {
-                            eprintln!("err skipping: {e}");
-                        }
-                        continue;
-                    }
-                    for line in m.lines() {
-                        self.push(line).await;
+            let m = tokio::select! {
+                m = stdout.recv() => m,
+                Ok(()) = link.changed() => {
+                    let now = *link.borrow_and_update();
+                    if now != Link::Connected || gone {
+                        gone = now != Link::Connected;
+                        self.send_message("server", &format!("<console {now}>")).await;
                     }
+                    continue;
                 }
+            };
+            let Some(m) = m else {
+                fail!("disconnected");
+            };
+            let lost = stdout.dropped() - dropped;
+            if lost > 0 {
+                output!("lost {lost} blocks");
+                dropped += lost;
+            }
+            for line in m.lines() {
+                let Some(msg) = get(line) else {
+                    unusual(line);
+                    continue;
+                };
+                // before discord, which can be slow
+                s.events
+                    .send_with(Event::now(msg.clone()), |e| s.chat.record(e))
+                    .await;
+                self.push(msg, s, staff).await;
             }
-            sleep(Duration::from_millis(100)).await;
         }
     }
 
-    pub async fn push(&self, msg: &str) {
-        match get(msg) {
-            Some(Message::Chat { player, content }) => {
+    pub async fn push(&self, msg: Message, s: &State, staff: Option<&Webhook<'_>>) {
+        match msg {
+            Message::Chat { player, content } => {
+                let content = MENTIONS.apply(&player, &content, Instant::now());
                 self.send_message(&player, &content).await;
             }
-            Some(Message::Join { player }) => {
-                self.send_message(&player, "<has joined the game>").await;
+            Message::Join { player, uuid } => {
+                println!("webhook: {player} joined");
+                AVATARS.joined(&player, &uuid);
+                self.queue(Out::Join(player)).await;
             }
-            Some(Message::Left { player }) => {
-                self.send_message(&player, "<has left the game>").await;
+            Message::Left { player } => {
+                println!("webhook: {player} left");
+                self.queue(Out::Left(player)).await;
             }
-            Some(Message::Load { map }) => {
+            Message::Load { map } => {
+                // the last game is over
+                if let Some(gif) = s.timelapse.finish() {
+                    self.queue(Out::Other(Box::new(
+                        Self::base()
+                            .username("server")
+                            .content("the last game:")
+                            .add_file(CreateAttachment::bytes(gif, "timelapse.gif")),
+                    )))
+                    .await;
+                }
                 self.send_message("server", &format!("loading map {map}"))
                     .await;
             }
-            _ => (),
+            Message::GameOver {
+                wave,
+                winner,
+                players,
+                map,
+            } => {
+                let how = match (wave, winner) {
+                    (_, Some(team)) => format!("{team} won"),
+                    (Some(wave), None) => format!("reached wave {wave}"),
+                    (None, None) => "its over".into(),
+                };
+                self.embed(
+                    "game over",
+                    format!("{how} on {map}, with {players} players online"),
+                    GOLD,
+                )
+                .await;
+            }
+            Message::Wave { wave } => {
+                self.send_message("server", &format!("-# wave {wave}"))
+                    .await;
+            }
+            Message::Kick { by, player } => {
+                self.embed("kick", format!("{by} kicked {player}"), ORANGE)
+                    .await;
+            }
+            Message::Ban { by, player } => {
+                self.embed("ban", format!("{by} banned {player}"), RED)
+                    .await;
+            }
+            Message::VotekickStart { player, target } => {
+                // still pings whoever `/votekick` is set to
+                let ping = MENTIONS.apply(&player, "/votekick", Instant::now());
+                self.queue(Out::Other(Box::new(
+                    Self::base().username(&player).content(ping).embed(
+                        CreateEmbed::new()
+                            .title("votekick")
+                            .description(format!("{player} wants {target} gone"))
+                            .color(ORANGE),
+                    ),
+                )))
+                .await;
+            }
+            Message::VotekickPass { target, minutes } => {
+                self.embed(
+                    "votekick passed",
+                    format!("{target} is banned for {minutes} minutes"),
+                    RED,
+                )
+                .await;
+            }
+            Message::VotekickFail { target } => {
+                self.embed("votekick failed", format!("{target} stays"), GREY)
+                    .await;
+            }
+            Message::Save { to } => {
+                let to = to.map_or(String::new(), |to| format!(" to {to}"));
+                self.send_message("server", &format!("-# saved{to}")).await;
+            }
+            Message::Crash { error, message } => {
+                let message = message.map_or(String::new(), |m| {
+                    format!("\n```\n{}\n```", m.replace("```", "`\u{200b}``"))
+                });
+                self.embed("exception", format!("`{error}`{message}"), RED)
+                    .await;
+            }
+            Message::AdminChat { player, content } => {
+                if let Some(staff) = staff {
+                    let content = content.strip_prefix("/a").unwrap_or(&content).trim();
+                    staff
+                        .send_message(&player, &crate::mentions::escape(content))
+                        .await;
+                }
+            }
         }
     }
-}
 
-#[derive(PartialEq, Eq, Debug, Clone)]
-pub enum Message {
-    Join { player: String },
-    Left { player: String },
-    Chat { player: String, content: String },
-    AdminChat { player: String, content: String },
-    Load { map: String },
+    async fn embed(&self, title: &str, description: String, color: (u8, u8, u8)) {
+        define_print!("webhook");
+        output!("{title}: {description}");
+        self.queue(Out::Other(Box::new(
+            Self::base().username("server").embed(
+                CreateEmbed::new()
+                    .title(title)
+                    .description(description)
+                    .color(color),
+            ),
+        )))
+        .await;
+    }
 }
 
-fn mention(line: &str) -> String {
-    const MODS: &str = "<@&1133416252791074877>";
-    const ADMINS: &str = "<@&1110088946374938715>";
-    line.replace("@Moderator", MODS)
-        .replace("@mods", MODS)
-        .replace("@Administrator", ADMINS)
-        .replace("@admin", ADMINS)
-        .replace("@bendn", "<@696196765564534825>")
-        .replace("@bende", "<@696196765564534825>")
-        .replace("@nile", "<@600014432298598400>")
-        .replace("@proto", "<@1173213085553660034>")
-        .replace("grief", "<@&1206743548838416455>")
-        .replace("/votekick", "<@&1206743639397630003>")
+const GOLD: (u8, u8, u8) = (251, 211, 103);
+const ORANGE: (u8, u8, u8) = (255, 165, 0);
+const RED: (u8, u8, u8) = (255, 69, 0);
+const GREY: (u8, u8, u8) = (112, 128, 144);
+
+#[derive(PartialEq, Eq, Debug, Clone, serde_derive::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    Join {
+        player: String,
+        /// not for everyone to see
+        #[serde(skip)]
+        uuid: String,
+    },
+    Left {
+        player: String,
+    },
+    Chat {
+        player: String,
+        content: String,
+    },
+    AdminChat {
+        player: String,
+        content: String,
+    },
+    Load {
+        map: String,
+    },
+    GameOver {
+        /// for wave games
+        wave: Option<u32>,
+        /// for pvp
+        winner: Option<String>,
+        players: u32,
+        map: String,
+    },
+    Wave {
+        wave: u32,
+    },
+    /// by an admin
+    Kick {
+        by: String,
+        player: String,
+    },
+    Ban {
+        by: String,
+        player: String,
+    },
+    VotekickStart {
+        player: String,
+        target: String,
+    },
+    VotekickPass {
+        target: String,
+        minutes: u32,
+    },
+    VotekickFail {
+        target: String,
+    },
+    Save {
+        to: Option<String>,
+    },
+    /// an exception. the stack trace isnt included
+    Crash {
+        error: String,
+        message: Option<String>,
+    },
 }
 
-fn get(line: &str) -> Option<Message> {
+/// lines that mean nothing on their own
+fn boring(line: &str) -> bool {
     macro_rules! s {
         ($line: expr, $($e:expr),+ $(,)?) => {
             $(
@@ -144,16 +478,65 @@ fn get(line: &str) -> Option<Message> {
             )+ false
         };
     }
-    if s!(
-        line,
-        [' ', '\t'],
-        "at",
-        "Lost command socket connection",
-        "Kicking connection"
-    ) {
+    line.trim().is_empty()
+        || s!(
+            line,
+            [' ', '\t'],
+            "at",
+            "Lost command socket connection",
+            "Kicking connection",
+            // the exception before it was already said
+            "Caused by",
+            "..."
+        )
+}
+
+/// lines nothing understood, once per shape, so patterns can be written for them
+fn unusual(line: &str) {
+    define_print!("webhook");
+    static SEEN: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+    if boring(line) {
+        return;
+    }
+    let shape = line
+        .chars()
+        .map(|c| if c.is_ascii_digit() { '#' } else { c })
+        .collect::<String>();
+    let mut seen = SEEN.lock().unwrap();
+    // dont grow forever
+    if seen.len() < 1000 && seen.insert(shape) {
+        output!("unrecognized: {line:?}");
+    }
+}
+
+/// lines that come whenever they like, so arent part of a commands output.
+pub fn unsolicited(line: &str) -> bool {
+    match get(line) {
+        Some(Message::Join { .. } | Message::Left { .. }) => true,
+        // numbered lists (like `lb`) and json look like chat too
+        Some(Message::Chat { player, .. } | Message::AdminChat { player, .. }) => {
+            !(player.starts_with('{') || player.bytes().all(|b| b.is_ascii_digit()))
+        }
+        _ => false,
+    }
+}
+
+fn get(line: &str) -> Option<Message> {
+    if boring(line) {
         return None;
     }
 
+    // before chat, since `java.lang.Error: message` looks like it
+    static CRASH: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r#"^(?:Exception in thread "[^"]*" )?((?:[a-z][\w$]*\.)+[A-Z][\w$]*(?:Exception|Error))(?:: (.*))?$"#).unwrap()
+    });
+    if let Some(captures) = CRASH.captures(line) {
+        return Some(Message::Crash {
+            error: captures.get(1).unwrap().as_str().into(),
+            message: captures.get(2).map(|m| m.as_str().into()),
+        });
+    }
+
     static HAS_UUID: LazyLock<Regex> =
         LazyLock::new(|| Regex::new(r"[a-zA-Z0-9+/]{22}==").unwrap());
 
@@ -161,7 +544,13 @@ fn get(line: &str) -> Option<Message> {
         let u = u.trim_start_matches('<');
         let c = c.trim_end_matches('>');
         if !(u.is_empty() || c.is_empty() || HAS_UUID.is_match(c) || HAS_UUID.is_match(u)) {
-            if c.starts_with("/a") {
+            if let Some(target) = c.strip_prefix("/votekick ") {
+                return Some(Message::VotekickStart {
+                    player: u.into(),
+                    target: strip_colors(target.trim()),
+                });
+            }
+            if c == "/a" || c.starts_with("/a ") {
                 return Some(Message::AdminChat {
                     player: u.into(),
                     content: mindustry_to_discord(c),
@@ -182,7 +571,10 @@ fn get(line: &str) -> Option<Message> {
         return Some(if captures.get(2).is_some() {
             Message::Left { player }
         } else {
-            Message::Join { player }
+            Message::Join {
+                player,
+                uuid: captures.get(3).unwrap().as_str().into(),
+            }
         });
     }
 
@@ -192,11 +584,67 @@ fn get(line: &str) -> Option<Message> {
             map: crate::bot::strip_colors(captures.get(1).unwrap().as_str()),
         });
     }
+
+    static GAME_OVER: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"^Game over! (?:Reached wave (\d+)|Team (.+) is victorious) with (\d+) players? online on map (.+)\.$").unwrap()
+    });
+    if let Some(captures) = GAME_OVER.captures(line) {
+        return Some(Message::GameOver {
+            wave: captures.get(1).and_then(|m| m.as_str().parse().ok()),
+            winner: captures.get(2).map(|m| strip_colors(m.as_str())),
+            players: captures[3].parse().ok()?,
+            map: strip_colors(&captures[4]),
+        });
+    }
+
+    static WAVE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^(?:Wave|Starting wave) (\d+)\.?$").unwrap());
+    if let Some(captures) = WAVE.captures(line) {
+        return Some(Message::Wave {
+            wave: captures[1].parse().ok()?,
+        });
+    }
+
+    static ADMIN: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^(.+) has (kicked|banned) (.+)\.$").unwrap());
+    if let Some(captures) = ADMIN.captures(line) {
+        let (by, player) = (strip_colors(&captures[1]), strip_colors(&captures[3]));
+        return Some(if &captures[2] == "kicked" {
+            Message::Kick { by, player }
+        } else {
+            Message::Ban { by, player }
+        });
+    }
+
+    static VOTE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"^Vote (?:passed\. (.+) will be banned from the server for (\d+) minutes|failed\. Not enough votes to kick (.+))\.$").unwrap()
+    });
+    // these are colored, as theyre also sent to players
+    let plain = strip_colors(line);
+    if let Some(captures) = VOTE.captures(&plain) {
+        return Some(match captures.get(3) {
+            Some(target) => Message::VotekickFail {
+                target: target.as_str().trim().into(),
+            },
+            None => Message::VotekickPass {
+                target: captures[1].trim().into(),
+                minutes: captures[2].parse().ok()?,
+            },
+        });
+    }
+
+    static SAVE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^(?:Autosaved|Saved to (.+?))\.$").unwrap());
+    if let Some(captures) = SAVE.captures(line) {
+        return Some(Message::Save {
+            to: captures.get(1).map(|m| m.as_str().into()),
+        });
+    }
     None
 }
 
 pub fn mindustry_to_discord(s: &str) -> String {
-    strip_colors(&mention(&crate::emoji::mindustry::to_discord(&unify(s))))
+    strip_colors(&crate::emoji::mindustry::to_discord(&unify(s)))
 }
 
 pub fn unify(s: &str) -> String {
@@ -205,18 +653,20 @@ pub fn unify(s: &str) -> String {
         .collect()
 }
 
+#[cfg(test)]
+macro_rules! test_line {
+    ($line:literal) => {
+        let got = get($line);
+        assert_eq!(got, None);
+    };
+    ($line:literal, $what:expr) => {
+        let got = get($line);
+        assert_eq!(got, Some($what));
+    };
+}
+
 #[test]
 fn style() {
-    macro_rules! test_line {
-        ($line:literal) => {
-            let got = get($line);
-            assert_eq!(got, None);
-        };
-        ($line:literal, $what:expr) => {
-            let got = get($line);
-            assert_eq!(got, Some($what));
-        };
-    }
     //unnamed
     test_line!("undefined");
     test_line!("Lost command socket connection: localhost/127.0.0.1:6859");
@@ -237,7 +687,10 @@ fn style() {
     );
     test_line!(
         "a has connected. [+41521zhHB8321xAbXYedw==]",
-        Message::Join { player: "a".into() }
+        Message::Join {
+            player: "a".into(),
+            uuid: "+41521zhHB8321xAbXYedw==".into()
+        }
     );
     test_line!(
         "a has disconnected. [+41521zhHB8321xAbXYedw==] (closed)",
@@ -259,8 +712,236 @@ fn style() {
     );
 }
 
+#[test]
+fn game() {
+    test_line!(
+        "Game over! Reached wave 31 with 4 players online on map Frozen Forest.",
+        Message::GameOver {
+            wave: Some(31),
+            winner: None,
+            players: 4,
+            map: "Frozen Forest".into()
+        }
+    );
+    test_line!(
+        "Game over! Team infected is victorious with 1 player online on map [#ff0000]plague.",
+        Message::GameOver {
+            wave: None,
+            winner: Some("infected".into()),
+            players: 1,
+            map: "plague".into()
+        }
+    );
+    test_line!("Wave 12", Message::Wave { wave: 12 });
+    test_line!(
+        "bendn has kicked nile.",
+        Message::Kick {
+            by: "bendn".into(),
+            player: "nile".into()
+        }
+    );
+    test_line!(
+        "[scarlet]bendn has banned [green]proto.",
+        Message::Ban {
+            by: "bendn".into(),
+            player: "proto".into()
+        }
+    );
+    test_line!(
+        "<nile: /votekick [green]proto>",
+        Message::VotekickStart {
+            player: "nile".into(),
+            target: "proto".into()
+        }
+    );
+    test_line!(
+        "[orange]Vote passed.[scarlet] proto[orange] will be banned from the server for 30 minutes.",
+        Message::VotekickPass {
+            target: "proto".into(),
+            minutes: 30
+        }
+    );
+    test_line!(
+        "[lightgray]Vote failed. Not enough votes to kick[orange] proto[lightgray].",
+        Message::VotekickFail {
+            target: "proto".into()
+        }
+    );
+    test_line!("Autosaved.", Message::Save { to: None });
+    test_line!(
+        "Saved to slot 0.",
+        Message::Save {
+            to: Some("slot 0".into())
+        }
+    );
+    test_line!(
+        "java.lang.NullPointerException: Cannot invoke \"mindustry.gen.Player.team()\" because \"p\" is null",
+        Message::Crash {
+            error: "java.lang.NullPointerException".into(),
+            message: Some("Cannot invoke \"mindustry.gen.Player.team()\" because \"p\" is null".into())
+        }
+    );
+    test_line!(
+        "Exception in thread \"main\" java.lang.OutOfMemoryError",
+        Message::Crash {
+            error: "java.lang.OutOfMemoryError".into(),
+            message: None
+        }
+    );
+    test_line!("Caused by: java.lang.RuntimeException: oops");
+    test_line!("\tat mindustry.core.NetServer.update(NetServer.java:1)");
+    test_line!("Kicking connection 10.0.0.1 / +41521zhHB8321xAbXYedw==; Reason: vote");
+    test_line!(
+        "<nile: /a proto is griefing>",
+        Message::AdminChat {
+            player: "nile".into(),
+            content: "/a proto is griefing".into()
+        }
+    );
+    test_line!(
+        "<nile: /about>",
+        Message::Chat {
+            player: "nile".into(),
+            content: "/about".into()
+        }
+    );
+    // still chat
+    test_line!(
+        "nile: Game over! Reached wave 1 with 1 players online on map x.",
+        Message::Chat {
+            player: "nile".into(),
+            content: "Game over! Reached wave 1 with 1 players online on map x.".into()
+        }
+    );
+}
+
+#[test]
+fn batching() {
+    let said = |u: &str, c: &str| Out::Said {
+        username: u.into(),
+        content: c.into(),
+    };
+    let mut pending = VecDeque::from([
+        said("nile", "a"),
+        said("nile", "b"),
+        said("bendn", "c"),
+        said("nile", "d"),
+        Out::Join("proto".into()),
+        Out::Join("nile".into()),
+        Out::Left("bendn".into()),
+        Out::Join("eris".into()),
+        Out::Other(Box::default()),
+        Out::Left("proto".into()),
+        said("nile", &"e".repeat(LONGEST)),
+        said("nile", "f"),
+    ]);
+    let mut got = vec![];
+    while let Some(out) = batch(&mut pending) {
+        got.push(match out {
+            Out::Said { username, content } => format!("{username}: {content}"),
+            Out::Join(p) => format!("+{p}"),
+            Out::Left(p) => format!("-{p}"),
+            Out::Other(_) => "other".into(),
+        });
+    }
+    assert_eq!(
+        got,
+        [
+            "nile: a\nb".to_owned(),
+            "bendn: c".into(),
+            "nile: d".into(),
+            "server: <proto and nile have joined the game>\n<bendn has left the game>\n<eris has joined the game>".into(),
+            "other".into(),
+            "-proto".into(),
+            format!("nile: {}", "e".repeat(LONGEST)),
+            "nile: f".into(),
+        ]
+    );
+}
+
 #[test]
 fn test_unify() {
     assert!(unify("grassྱྊၔ") == "grass");
     assert!(unify("иди к черту") == "иди к черту");
 }
+
+#[cfg(test)]
+impl<'a> Webhook<'a> {
+    /// one that never asks discord for anything (so dont [`drain`](Self::drain) it)
+    fn fake(http: &'a Http) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE);
+        Self {
+            inner: serde_json::from_value(serde_json::json!({
+                "id": "1",
+                "type": 1,
+                "channel_id": "1",
+                "token": "fake",
+            }))
+            .unwrap(),
+            http,
+            tx,
+            rx: tokio::sync::Mutex::new(rx),
+        }
+    }
+}
+
+#[tokio::test]
+async fn relay() {
+    let fake = crate::fake::Fake::script(crate::fake::CONSOLE)
+        .on("status", "nile: hello\n57 TPS / 274 MB / 2 PLAYERS")
+        .spawn()
+        .await;
+    let (s, stdout) = fake.server();
+    let http = Http::new("");
+    let wh = Webhook::fake(&http);
+    fake.say("nile has connected. [JjvYb8x7FWSV+zA0VsuHlw==]");
+    let check = async {
+        // the chat came in the middle of the answer, and still gets relayed
+        let status = s.console.request("status").await.unwrap();
+        assert_eq!(status, "57 TPS / 274 MB / 2 PLAYERS");
+        let mut rx = wh.rx.lock().await;
+        let mut got = vec![];
+        while got.len() < 2 {
+            got.push(match rx.recv().await.unwrap() {
+                Out::Said { username, content } => format!("{username}: {content}"),
+                Out::Join(p) => format!("+{p}"),
+                out => panic!("{out:?}"),
+            });
+        }
+        got.sort();
+        got
+    };
+    let got = tokio::select! {
+        () = wh.link(stdout, &s, None) => unreachable!(),
+        got = check => got,
+    };
+    assert_eq!(got, ["+nile", "nile: hello"]);
+}
+
+#[tokio::test]
+async fn full() {
+    use std::time::Duration;
+    let http = Http::new("");
+    let wh = Webhook::fake(&http);
+    for i in 1..QUEUE {
+        wh.queue(Out::Join(i.to_string())).await;
+    }
+    // sent ones go after whats queued too
+    wh.send(|m| m.content("attacked")).await;
+    // waits for room, rather than losing it
+    let more = wh.queue(Out::Join("nile".into()));
+    tokio::pin!(more);
+    assert!(tokio::time::timeout(Duration::from_millis(50), &mut more)
+        .await
+        .is_err());
+    let mut rx = wh.rx.lock().await;
+    assert!(matches!(rx.recv().await, Some(Out::Join(p)) if p == "1"));
+    more.await;
+    let mut rest = vec![];
+    while let Ok(out) = rx.try_recv() {
+        rest.push(out);
+    }
+    assert_eq!(rest.len(), QUEUE);
+    assert!(matches!(&rest[QUEUE - 2], Out::Other(_)));
+    assert!(matches!(&rest[QUEUE - 1], Out::Join(p) if p == "nile"));
+}